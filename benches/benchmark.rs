@@ -2,7 +2,7 @@
 extern crate criterion;
 
 use criterion::Criterion;
-use prose::{FormatOpts, Reformatter};
+use prose::{FormatOpts, Reformatter, Solver};
 use std::hint::black_box;
 
 fn bench_reformatting(c: &mut Criterion) {
@@ -26,5 +26,32 @@ fn bench_reformatting(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, bench_reformatting);
+fn bench_solvers(c: &mut Criterion) {
+    // one long paragraph, which is where the choice of solver matters
+    let data = include_str!("../tests/data/inputs/plain.txt")
+        .replace("\n\n", "\n")
+        .repeat(20);
+    for (name, solver) in [
+        ("shortest path", Solver::ShortestPath),
+        ("dp", Solver::DynamicProgramming),
+    ] {
+        let opts = FormatOpts {
+            max_length: 72,
+            solver,
+            ..Default::default()
+        };
+        c.bench_function(&format!("long paragraph, {}", name), |b| {
+            b.iter(|| prose::reformat(&opts, black_box(&data)));
+        });
+        let opts = FormatOpts {
+            reduce_jaggedness: true,
+            ..opts
+        };
+        c.bench_function(&format!("long paragraph with fit, {}", name), |b| {
+            b.iter(|| prose::reformat(&opts, black_box(&data)));
+        });
+    }
+}
+
+criterion_group!(benches, bench_reformatting, bench_solvers);
 criterion_main!(benches);
@@ -1,6 +1,6 @@
 use itertools::Itertools;
 use std::borrow::Cow;
-use unicode_width::UnicodeWidthStr;
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
 
 pub type Token<'a> = Cow<'a, str>;
 
@@ -8,19 +8,66 @@ pub trait Width {
     fn width(&self) -> usize;
 }
 
+impl Width for str {
+    fn width(&self) -> usize {
+        self.graphemes(true)
+            // soft hyphens are only visible where a line is broken
+            .filter(|&g| g != "\u{ad}")
+            .map(unicode_width::UnicodeWidthStr::width)
+            .sum()
+    }
+}
+
 impl<'a> Width for Token<'a> {
     fn width(&self) -> usize {
-        match self {
-            Token::Borrowed(s) => UnicodeWidthStr::width(*s),
-            Token::Owned(s) => UnicodeWidthStr::width(s.as_str()),
-        }
+        self.as_ref().width()
     }
 }
 
+/// Whether byte offset `i` in `s` falls between two grapheme clusters
+pub fn is_grapheme_boundary(s: &str, i: usize) -> bool {
+    GraphemeCursor::new(i, s.len(), true)
+        .is_boundary(s, 0)
+        .unwrap_or(false)
+}
+
+/// Whether `c` belongs to Chinese or Japanese text, which is written without
+/// spaces between words: lines may be broken next to it and are joined back
+/// together without a space
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{2E80}'..='\u{312F}' // radicals, punctuation, kana, bopomofo
+        | '\u{3190}'..='\u{9FFF}' // (skipping Hangul jamo) up to unified ideographs
+        | '\u{F900}'..='\u{FAFF}' // compatibility ideographs
+        | '\u{FE30}'..='\u{FE4F}' // compatibility forms
+        | '\u{FF01}'..='\u{FF60}' // fullwidth forms
+        | '\u{FFE0}'..='\u{FFE6}'
+        | '\u{20000}'..='\u{3FFFD}' // supplementary ideographs
+    )
+}
+
+/// Add the words of a continuation line. If the line break falls between two
+/// CJK characters there was no space there, so the first word continues the
+/// last one.
+pub fn extend_words<'a>(words: &mut Vec<Token<'a>>, line: &'a str) {
+    let mut iter = line.split_whitespace().peekable();
+    if let (Some(last), Some(first)) = (words.last_mut(), iter.peek())
+        && last.chars().next_back().is_some_and(is_cjk)
+        && first.chars().next().is_some_and(is_cjk)
+    {
+        last.to_mut().push_str(first);
+        iter.next();
+    }
+    words.extend(iter.map(Token::Borrowed));
+}
+
 #[derive(Debug)]
 pub struct Block<'a> {
     pub prefix: &'a str,
     pub suffix: &'a str,
+    /// List marker (with its indentation) that starts the first line only;
+    /// continuation lines are indented by its width instead
+    pub marker: &'a str,
     pub words: Vec<Token<'a>>,
     pub newline_after: bool,
 }
@@ -51,6 +98,33 @@ fn get_quotes(line: &str) -> (usize, &str) {
     }
 }
 
+/// Byte length of the list marker at the start of a line (a bullet like `-`
+/// or `*`, or an enumerator like `1.` or `a)`), including its indentation and
+/// the whitespace that follows it
+pub(crate) fn list_marker(line: &str) -> Option<usize> {
+    let indentation = line.len() - line.trim_start().len();
+    let rest = &line[indentation..];
+    let first = rest.chars().next()?;
+    let len = match first {
+        '-' | '*' | '+' | '•' => first.len_utf8(),
+        c if c.is_ascii_digit() => {
+            let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+            match rest[digits..].chars().next() {
+                Some('.') | Some(')') if digits <= 9 => digits + 1,
+                _ => return None,
+            }
+        }
+        c if c.is_ascii_alphabetic() && rest[1..].starts_with(')') => 2,
+        _ => return None,
+    };
+    let after = &rest[len..];
+    let spacing = after.len() - after.trim_start().len();
+    if spacing == 0 || after.trim().is_empty() {
+        return None;
+    }
+    Some(indentation + len + spacing)
+}
+
 fn collect_blocks<'a>(lines: &[&'a str], prefix: &'a str, suffix: &'a str) -> Vec<Block<'a>> {
     let mut blocks: Vec<Block> = vec![];
     let groups = lines
@@ -59,13 +133,27 @@ fn collect_blocks<'a>(lines: &[&'a str], prefix: &'a str, suffix: &'a str) -> Ve
         .chunk_by(|l| l.trim().is_empty());
     for (_, line_group) in &groups {
         let mut words: Vec<Token<'a>> = vec![];
+        let mut marker = "";
         let mut newline_after = false;
         for (i, line) in line_group.enumerate() {
             if line.trim().is_empty() {
                 newline_after = true;
                 continue;
             }
-            if i == 0 {
+            if let Some(len) = list_marker(line) {
+                // each list item is its own block
+                if !words.is_empty() {
+                    blocks.push(Block {
+                        prefix,
+                        suffix,
+                        marker,
+                        words: std::mem::take(&mut words),
+                        newline_after: false,
+                    });
+                }
+                marker = &line[0..len];
+                words.extend(line[len..].split_whitespace().map(Token::Borrowed));
+            } else if i == 0 {
                 let indentation = line.chars().take_while(|&c| c.is_whitespace()).count();
                 let mut iter = line.split_whitespace();
                 if let Some(first_word) = iter.next() {
@@ -73,12 +161,13 @@ fn collect_blocks<'a>(lines: &[&'a str], prefix: &'a str, suffix: &'a str) -> Ve
                 }
                 words.extend(iter.map(Token::Borrowed));
             } else {
-                words.extend(line.split_whitespace().map(Token::Borrowed));
+                extend_words(&mut words, line);
             }
         }
         blocks.push(Block {
             prefix,
             suffix,
+            marker,
             words,
             newline_after,
         });
@@ -86,45 +175,79 @@ fn collect_blocks<'a>(lines: &[&'a str], prefix: &'a str, suffix: &'a str) -> Ve
     blocks
 }
 
+/// A line kept exactly as it is (apart from trailing whitespace), such as the
+/// delimiters of a block comment
+fn verbatim(line: &str) -> Block<'_> {
+    // a block without words is written as just its trimmed prefix
+    Block {
+        prefix: line,
+        suffix: "",
+        marker: "",
+        words: vec![],
+        newline_after: true,
+    }
+}
+
+/// Whether `line` opens a block comment and holds nothing else: `/*`, `/**`
+/// or `/*!`
+pub(crate) fn is_comment_opener(line: &str) -> bool {
+    line.trim()
+        .strip_prefix("/*")
+        .is_some_and(|rest| rest.chars().all(|c| c == '*' || c == '!'))
+}
+
+/// Whether `line` closes a block comment and holds nothing else
+pub(crate) fn is_comment_closer(line: &str) -> bool {
+    line.trim()
+        .strip_suffix("*/")
+        .is_some_and(|rest| rest.chars().all(|c| c == '*'))
+}
+
 pub struct Input<'a> {
     lines: Vec<&'a str>,
 }
 
 impl<'a> Input<'a> {
     fn longest_common_affix(&self, dir: Dir) -> &'a str {
-        if self.lines.is_empty() {
+        let Some(first) = self.lines.first() else {
             return "";
-        }
-        let mut ret = None;
-        let mut i = 0;
-        'outer: loop {
-            let mut range = None;
-            for s in self.lines.iter() {
-                if i >= s.len() {
-                    break 'outer;
-                }
-                let (start, finish) = match dir {
-                    Dir::Forward => (0, i + 1),
-                    Dir::Reverse => ((s.len() - i - 1), s.len()),
-                };
-                if !s.is_char_boundary(start) || !s.is_char_boundary(finish) {
-                    i += 1;
-                    continue 'outer;
-                }
-                match range {
-                    None => {
-                        range = Some(&s[start..finish]);
+        };
+        // only whole grapheme clusters, so a shared base character isn't
+        // separated from the combining marks that follow it on some lines.
+        // Each cluster of the first line is compared with just the same bytes
+        // of the others, so this stays linear in the length of the lines.
+        let mut len = 0;
+        match dir {
+            Dir::Forward => {
+                for g in first.graphemes(true) {
+                    let end = len + g.len();
+                    let shared = self
+                        .lines
+                        .iter()
+                        .all(|s| s.get(len..end) == Some(g) && is_grapheme_boundary(s, end));
+                    if !shared {
+                        break;
                     }
-                    Some(prev) if prev != &s[start..finish] => {
-                        break 'outer;
+                    len = end;
+                }
+                &first[..len]
+            }
+            Dir::Reverse => {
+                for g in first.graphemes(true).rev() {
+                    let end = len + g.len();
+                    let shared = self.lines.iter().all(|s| {
+                        s.len() >= end
+                            && s.get(s.len() - end..s.len() - len) == Some(g)
+                            && is_grapheme_boundary(s, s.len() - end)
+                    });
+                    if !shared {
+                        break;
                     }
-                    _ => continue,
+                    len = end;
                 }
+                &first[first.len() - len..]
             }
-            ret = range;
-            i += 1;
         }
-        ret.unwrap_or("")
     }
 
     pub fn analyze_quotes(&self) -> Option<Vec<Block<'a>>> {
@@ -143,6 +266,16 @@ impl<'a> Input<'a> {
                 }
                 current_quote = this_quote;
                 idx = i;
+            } else if let Some(space) = this_quote
+                .1
+                .strip_prefix(current_quote.1)
+                .and_then(|rest| rest.chars().next())
+                .filter(|_| current_quote.1.ends_with('>'))
+            {
+                // a run that starts with an empty quoted line gets the space
+                // after the markers from the first line with text
+                let len = current_quote.1.len() + space.len_utf8();
+                current_quote.1 = &line[0..len];
             }
         }
         if has_quotes {
@@ -159,7 +292,7 @@ impl<'a> Input<'a> {
         if self.lines.is_empty() {
             return None;
         }
-        let comment_styles = ["///", "//", "#", ";;", ";", "--"];
+        let comment_styles = ["///", "//!", "//", "#", ";;;", ";;", ";", "--"];
         let first = self.lines[0];
         let start = first.find(|c: char| !c.is_ascii_whitespace())?;
         let comment_style = comment_styles
@@ -171,8 +304,12 @@ impl<'a> Input<'a> {
         let pat = self
             .lines
             .iter()
-            .find(|line| line.len() > bare.len())
-            .map(|line| &line[0..(bare.len() + 1).min(line.len())])
+            .find_map(|line| {
+                // up to the end of the character after the marker, which may
+                // take more than one byte
+                let next = line.get(bare.len()..)?.chars().next()?;
+                Some(&line[0..bare.len() + next.len_utf8()])
+            })
             .unwrap_or(bare);
         if self
             .lines
@@ -186,6 +323,65 @@ impl<'a> Input<'a> {
         }
     }
 
+    /// A C-style block comment, with its opening and closing delimiters on
+    /// lines of their own and usually a ` * ` leader on the lines between.
+    /// Javadoc tags like `@param` each start a new paragraph.
+    pub fn analyze_block_comment(&self) -> Option<Vec<Block<'a>>> {
+        let (&first, rest) = self.lines.split_first()?;
+        let (&last, body) = rest.split_last()?;
+        if !is_comment_opener(first) || !is_comment_closer(last) {
+            return None;
+        }
+        let star = body.iter().find_map(|line| {
+            let start = line.find(|c: char| !c.is_whitespace())?;
+            line[start..].starts_with('*').then(|| &line[..start + 1])
+        });
+        let (leader, prefix) = match star {
+            // the leader and the space after it, from the first line with text
+            Some(leader) => {
+                let prefix = body
+                    .iter()
+                    .find(|line| line.len() > leader.len() && line.starts_with(leader))
+                    .map(|line| {
+                        let space = line[leader.len()..].starts_with(' ') as usize;
+                        &line[..leader.len() + space]
+                    })
+                    .unwrap_or(leader);
+                (leader, prefix)
+            }
+            // without a leader, the indentation shared by the lines with text
+            None => {
+                let indentation = body
+                    .iter()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| &line[..line.len() - line.trim_start().len()])
+                    .min_by_key(|indentation| indentation.len())
+                    .unwrap_or("");
+                (indentation, indentation)
+            }
+        };
+        if !body
+            .iter()
+            .all(|line| line.starts_with(prefix) || line.trim_end() == leader.trim_end())
+        {
+            return None;
+        }
+
+        let mut blocks = vec![verbatim(first)];
+        let mut start = 0;
+        for i in 1..=body.len() {
+            let starts_tag = body
+                .get(i)
+                .is_some_and(|line| trim_off(line, prefix, "").trim_start().starts_with('@'));
+            if i == body.len() || starts_tag {
+                blocks.extend(collect_blocks(&body[start..i], prefix, ""));
+                start = i;
+            }
+        }
+        blocks.push(verbatim(last));
+        Some(blocks)
+    }
+
     pub fn analyze_surround(&self) -> Option<Vec<Block<'a>>> {
         let mut prefix = self.longest_common_affix(Dir::Forward);
         let mut suffix = self.longest_common_affix(Dir::Reverse);
@@ -195,6 +391,20 @@ impl<'a> Input<'a> {
             suffix = "";
         }
 
+        // Lines that happen to end with the same sentence punctuation aren't framed
+        if suffix.chars().all(|c| ".!?。！？".contains(c)) {
+            suffix = "";
+        }
+
+        // Lines that all start with the same bullet are a list, not a border.
+        // An indented `*` is left alone, as it's the leader in block comments.
+        let bullet = prefix.trim();
+        if prefix.ends_with(char::is_whitespace)
+            && (matches!(bullet, "-" | "+" | "•") || (bullet == "*" && prefix.starts_with('*')))
+        {
+            prefix = &prefix[0..(prefix.len() - prefix.trim_start().len())];
+        }
+
         let collected = collect_blocks(&self.lines, prefix, suffix);
 
         Some(collected)
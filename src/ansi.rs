@@ -0,0 +1,88 @@
+use crate::analysis::Width;
+use std::ops::Range;
+
+const ESC: char = '\x1b';
+
+/// Byte ranges of the terminal escape sequences in `s`: CSI sequences like
+/// SGR colors, OSC sequences like OSC 8 hyperlinks, and two-byte escapes
+pub fn escapes(s: &str) -> Vec<Range<usize>> {
+    let mut ranges = vec![];
+    let mut pos = 0;
+    while let Some(found) = s[pos..].find(ESC) {
+        let start = pos + found;
+        let rest = &s[start + 1..];
+        let len = match rest.chars().next() {
+            Some('[') => rest[1..]
+                .find(|c: char| ('\x40'..='\x7e').contains(&c))
+                .map_or(rest.len(), |i| i + 2),
+            Some(']') => match rest.find(['\x07', ESC]) {
+                Some(i) if rest[i..].starts_with(ESC) => (i + 2).min(rest.len()),
+                Some(i) => i + 1,
+                None => rest.len(),
+            },
+            Some(c) => c.len_utf8(),
+            None => 0,
+        };
+        pos = start + 1 + len;
+        ranges.push(start..pos);
+    }
+    ranges
+}
+
+/// Display width of `s`, not counting escape sequences
+pub fn width(s: &str) -> usize {
+    let mut total = 0;
+    let mut pos = 0;
+    for range in escapes(s) {
+        total += s[pos..range.start].width();
+        pos = range.end;
+    }
+    total + s[pos..].width()
+}
+
+/// The graphic rendition and hyperlink in effect at some point in the text
+#[derive(Default)]
+pub struct Style {
+    sgr: String,
+    link: Option<String>,
+}
+
+impl Style {
+    /// Apply the escape sequences in `line`
+    pub fn update(&mut self, line: &str) {
+        for range in escapes(line) {
+            let seq = &line[range];
+            if let Some(params) = seq.strip_prefix("\x1b[").and_then(|s| s.strip_suffix('m')) {
+                if params.is_empty() || params == "0" || params.starts_with("0;") {
+                    self.sgr.clear();
+                }
+                if !params.is_empty() && params != "0" {
+                    self.sgr.push_str(seq);
+                }
+            } else if let Some(link) = seq.strip_prefix("\x1b]8;") {
+                let target = link.split_once(';').map_or("", |(_, rest)| rest);
+                let target = target.trim_end_matches(['\x07', '\\', ESC]);
+                self.link = (!target.is_empty()).then(|| seq.to_string());
+            }
+        }
+    }
+
+    /// Sequences that restore this style at the start of a line
+    pub fn restore(&self) -> String {
+        let mut s = self.sgr.clone();
+        if let Some(link) = &self.link {
+            s.push_str(link);
+        }
+        s
+    }
+
+    /// Sequences that end this style before the end of a line
+    pub fn end(&self) -> &'static str {
+        match (self.sgr.is_empty(), self.link.is_none()) {
+            (true, true) => "",
+            (false, true) => "\x1b[0m",
+            (true, false) => "\x1b]8;;\x1b\\",
+            (false, false) => "\x1b]8;;\x1b\\\x1b[0m",
+        }
+    }
+}
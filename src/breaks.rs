@@ -0,0 +1,186 @@
+use crate::analysis::{Token, Width, is_cjk, is_grapheme_boundary};
+use crate::ansi;
+use crate::reformat::LongWordPolicy;
+use unicode_linebreak::{BreakOpportunity, linebreaks};
+use unicode_segmentation::UnicodeSegmentation;
+
+const SOFT_HYPHEN: char = '\u{ad}';
+
+/// Characters in URLs and paths that a line may be broken after
+const URL_BREAK_CHARS: [char; 4] = ['/', '?', '&', '-'];
+
+/// How a piece is joined to the one before it
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Join {
+    /// A space between two words
+    Space,
+    /// Two parts of the same word: the given (invisible) text when they stay on
+    /// the same line, a hyphen when the line is broken between them
+    Hyphen(&'static str),
+    /// Nothing either way, as between two ideographs
+    Direct,
+}
+
+/// A word, or the part of one between two places where a line may be broken
+#[derive(Debug)]
+pub struct Piece<'a> {
+    pub text: &'a str,
+    pub join: Join,
+}
+
+/// Split `text` at the given byte offsets
+fn split_at(text: &str, points: impl IntoIterator<Item = usize>) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    for point in points {
+        parts.push(&text[start..point]);
+        start = point;
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Line break opportunities (UAX #14) inside a word, limited to those next to
+/// characters from scripts that are written without spaces (which includes
+/// CJK punctuation). UAX #14 also allows breaks inside URLs, paths and
+/// hyphenated words; those are left to `LongWordPolicy` and hyphenation, so
+/// that a word only comes apart when asked. Thai and other scripts that need a
+/// dictionary get no breaks from UAX #14 at all.
+fn unspaced_breaks(word: &str) -> Vec<usize> {
+    let indentation = word.len() - word.trim_start().len();
+    linebreaks(word)
+        .filter(|&(i, op)| {
+            op == BreakOpportunity::Allowed && i > indentation && is_grapheme_boundary(word, i)
+        })
+        .filter(|&(i, _)| {
+            word[..i].chars().next_back().is_some_and(is_cjk)
+                || word[i..].chars().next().is_some_and(is_cjk)
+        })
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(feature = "hyphenation")]
+fn hyphenation_points(part: &str) -> Vec<usize> {
+    use hyphenation::{Hyphenator, Language, Load, Standard};
+    use std::sync::OnceLock;
+
+    static DICTIONARY: OnceLock<Option<Standard>> = OnceLock::new();
+
+    // URLs, paths, code and words that already have hyphens are left alone
+    if part.contains(|c: char| c.is_ascii_digit() || "/:@`_-".contains(c)) {
+        return vec![];
+    }
+    let trimmed = part.trim_start();
+    let indentation = part.len() - trimmed.len();
+    match DICTIONARY.get_or_init(|| Standard::from_embedded(Language::EnglishUS).ok()) {
+        Some(dictionary) => dictionary
+            .hyphenate(trimmed)
+            .breaks
+            .into_iter()
+            .map(|b| b + indentation)
+            .filter(|&b| is_grapheme_boundary(part, b))
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[cfg(not(feature = "hyphenation"))]
+fn hyphenation_points(_part: &str) -> Vec<usize> {
+    vec![]
+}
+
+/// Split words into pieces wherever a line may be broken inside them: between
+/// characters of scripts like Chinese and Japanese, at soft hyphens and, if
+/// `hyphenate` is set, at the points that the hyphenation patterns allow
+pub fn pieces<'a>(words: &'a [Token<'a>], hyphenate: bool) -> Vec<Piece<'a>> {
+    let mut pieces = vec![];
+    for word in words {
+        let mut join = Join::Space;
+        for run in split_at(word, unspaced_breaks(word)) {
+            for part in run.split(SOFT_HYPHEN).filter(|p| !p.is_empty()) {
+                let points = if hyphenate {
+                    hyphenation_points(part)
+                } else {
+                    vec![]
+                };
+                for text in split_at(part, points) {
+                    pieces.push(Piece { text, join });
+                    join = Join::Hyphen("");
+                }
+                join = Join::Hyphen("\u{ad}");
+            }
+            join = Join::Direct;
+        }
+    }
+    pieces
+}
+
+/// Put pieces back together into words
+pub fn words(pieces: &[Piece]) -> Vec<String> {
+    let mut words: Vec<String> = vec![];
+    for piece in pieces {
+        match (piece.join, words.last_mut()) {
+            (Join::Hyphen(glue), Some(word)) => {
+                word.push_str(glue);
+                word.push_str(piece.text);
+            }
+            (Join::Direct, Some(word)) => word.push_str(piece.text),
+            _ => words.push(piece.text.to_string()),
+        }
+    }
+    words
+}
+
+/// Where a word too wide for a line may be broken under `policy`
+fn long_word_breaks(text: &str, policy: LongWordPolicy) -> Vec<usize> {
+    let indentation = text.len() - text.trim_start().len();
+    match policy {
+        LongWordPolicy::Break => text
+            .grapheme_indices(true)
+            .map(|(i, _)| i)
+            .filter(|&i| i > indentation)
+            .collect(),
+        // after a run of them, so that `//` stays together
+        LongWordPolicy::BreakAtUrlPoints => text
+            .char_indices()
+            .filter(|&(i, c)| {
+                i > indentation
+                    && !URL_BREAK_CHARS.contains(&c)
+                    && text[..i].ends_with(URL_BREAK_CHARS)
+            })
+            .map(|(i, _)| i)
+            .collect(),
+        LongWordPolicy::Allow | LongWordPolicy::Fail => vec![],
+    }
+}
+
+/// Split the pieces that are wider than `target` as `policy` allows. The parts
+/// are joined directly, without a hyphen, and escape sequences are kept whole
+/// if `ansi` is set.
+pub fn split_long<'a>(
+    pieces: Vec<Piece<'a>>,
+    policy: LongWordPolicy,
+    target: usize,
+    ansi: bool,
+) -> Vec<Piece<'a>> {
+    let width = |s: &str| if ansi { ansi::width(s) } else { s.width() };
+    let mut split = Vec::with_capacity(pieces.len());
+    for piece in pieces {
+        if width(piece.text) <= target {
+            split.push(piece);
+            continue;
+        }
+        let mut points = long_word_breaks(piece.text, policy);
+        if ansi {
+            let escapes = ansi::escapes(piece.text);
+            points.retain(|&i| !escapes.iter().any(|e| e.start < i && i < e.end));
+        }
+        let mut join = piece.join;
+        for text in split_at(piece.text, points) {
+            split.push(Piece { text, join });
+            join = Join::Direct;
+        }
+    }
+    split
+}
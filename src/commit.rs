@@ -0,0 +1,133 @@
+use crate::analysis::{Width, list_marker};
+use crate::error::{Diagnostic, Error};
+use crate::reformat::{self, FormatMode, FormatOpts, Rewrapped};
+use std::ops::Range;
+
+/// The line `git commit --verbose` puts above the diff. It and everything
+/// after it are left alone.
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// Subject lines wider than this get a warning
+const SUBJECT_LIMIT: usize = 50;
+
+/// Whether `line` is a trailer, like `Signed-off-by: A U Thor <author@example.com>`
+fn is_trailer(line: &str) -> bool {
+    line.split_once(':').is_some_and(|(token, value)| {
+        !token.is_empty()
+            && token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+            && (value.is_empty() || value.starts_with(' '))
+    })
+}
+
+/// Rewrap the body of a commit message. The subject line is never wrapped,
+/// but gets a blank line after it if it's missing. Trailers, `#` comments and
+/// everything from the scissors line on are left alone, and so are indented
+/// paragraphs, which are usually code.
+pub(crate) fn rewrapped(opts: &FormatOpts, input: &str) -> Result<Vec<Rewrapped>, Error> {
+    let mut starts = vec![0];
+    starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+    if starts.last() == Some(&input.len()) {
+        starts.pop();
+    }
+    let line_range = |n: usize| {
+        let end = starts.get(n + 1).map_or(input.len(), |&next| next - 1);
+        let end = if input[..end].ends_with('\r') {
+            end - 1
+        } else {
+            end
+        };
+        starts[n]..end
+    };
+    let lines: Vec<&str> = (0..starts.len()).map(|n| &input[line_range(n)]).collect();
+
+    // runs of lines that are neither blank nor comments
+    let mut paragraphs: Vec<Range<usize>> = vec![];
+    for (n, line) in lines.iter().enumerate() {
+        if *line == SCISSORS {
+            break;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match paragraphs.last_mut() {
+            Some(para) if para.end == n => para.end = n + 1,
+            _ => paragraphs.push(n..n + 1),
+        }
+    }
+    // trailers at the end of the last paragraph, with any continuation lines,
+    // whether or not a blank line comes before them. The subject line is
+    // never one.
+    let count = paragraphs.len();
+    if let Some(last) = paragraphs.last_mut() {
+        let first = last.start + usize::from(count == 1);
+        let mut start = last.end;
+        while start > first
+            && (is_trailer(lines[start - 1]) || lines[start - 1].starts_with(char::is_whitespace))
+        {
+            start -= 1;
+        }
+        while start < last.end && !is_trailer(lines[start]) {
+            start += 1;
+        }
+        last.end = start;
+        if last.start == last.end {
+            paragraphs.pop();
+        }
+    }
+
+    let mut regions = vec![];
+    if !paragraphs.is_empty() {
+        let subject = paragraphs[0].start;
+        let mut text = lines[subject].to_string();
+        if lines
+            .get(subject + 1)
+            .is_some_and(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        {
+            // the body or trailers start on the next line; keep them apart
+            text.push('\n');
+        }
+        paragraphs[0].start += 1;
+        if paragraphs[0].start == paragraphs[0].end {
+            paragraphs.remove(0);
+        }
+        let width = lines[subject].width();
+        let diagnostics = if width > SUBJECT_LIMIT {
+            vec![Diagnostic::LongSubject {
+                line: 1,
+                width,
+                limit: SUBJECT_LIMIT,
+            }]
+        } else {
+            vec![]
+        };
+        regions.push(Rewrapped {
+            range: line_range(subject),
+            text,
+            diagnostics,
+        });
+    }
+
+    let opts = FormatOpts {
+        format_mode: FormatMode::PlainText,
+        ..opts.clone()
+    };
+    paragraphs.retain(|para| {
+        let first = lines[para.start];
+        !first.starts_with(char::is_whitespace) || list_marker(first).is_some()
+    });
+    let body = crate::map_in_order(paragraphs, |para| {
+        let range = line_range(para.start).start..line_range(para.end - 1).end;
+        let (text, diagnostics) = reformat::with_diagnostics(&opts, |opts| {
+            reformat::reformat_unchecked(opts, &lines[para].join("\n"))
+        });
+        Ok(Rewrapped {
+            range,
+            text: text?,
+            diagnostics,
+        })
+    });
+    for para in body {
+        regions.push(para?);
+    }
+    Ok(regions)
+}
@@ -0,0 +1,297 @@
+use crate::analysis::{Block, Token, extend_words, list_marker};
+use crate::error::Error;
+use crate::reformat::{self, FormatOpts, Reformatter};
+
+/// Google style section headers, like `Args:`
+const GOOGLE_SECTIONS: &[&str] = &[
+    "Args",
+    "Arguments",
+    "Attention",
+    "Attributes",
+    "Caution",
+    "Danger",
+    "Example",
+    "Examples",
+    "Hint",
+    "Important",
+    "Keyword Args",
+    "Keyword Arguments",
+    "Methods",
+    "Note",
+    "Notes",
+    "Other Parameters",
+    "Parameters",
+    "Raises",
+    "References",
+    "Return",
+    "Returns",
+    "See Also",
+    "Tip",
+    "Todo",
+    "Warning",
+    "Warnings",
+    "Warns",
+    "Yield",
+    "Yields",
+];
+
+/// Google style sections with an entry for each parameter, attribute or
+/// exception, each starting a line
+const GOOGLE_ITEM_SECTIONS: &[&str] = &[
+    "Args",
+    "Arguments",
+    "Attributes",
+    "Keyword Args",
+    "Keyword Arguments",
+    "Methods",
+    "Other Parameters",
+    "Parameters",
+    "Raises",
+    "Warns",
+];
+
+/// NumPy style sections made of `name : type` lines, each followed by an
+/// indented description
+const NUMPY_ITEM_SECTIONS: &[&str] = &[
+    "Attributes",
+    "Methods",
+    "Other Parameters",
+    "Parameters",
+    "Raises",
+    "Receives",
+    "Returns",
+    "Warns",
+    "Yields",
+];
+
+/// Sections of code examples, which are never rewrapped
+const EXAMPLE_SECTIONS: &[&str] = &["Example", "Examples"];
+
+/// The section of a docstring that the lines being read are in
+struct Section {
+    /// Indentation of its header
+    indent: usize,
+    /// Whether it's a NumPy style section, with an underlined header
+    numpy: bool,
+    /// Whether it's made of entries, like parameters
+    items: bool,
+    /// Whether its lines are kept as they are
+    verbatim: bool,
+    /// Indentation of a Google style section's entries, once seen
+    item_indent: Option<usize>,
+}
+
+/// A paragraph or entry to rewrap
+struct Text<'a> {
+    /// Indentation of the first line
+    indent: &'a str,
+    /// List marker after the indentation, and the spaces after it
+    marker: &'a str,
+    /// Whether it's an entry, like `x (int): ...` or `:param x: ...`, whose
+    /// continuation lines hang below it
+    item: bool,
+    /// Indentation of the continuation lines, once one is seen
+    hang: Option<&'a str>,
+    words: Vec<Token<'a>>,
+}
+
+impl Text<'_> {
+    /// Whether a line indented by `indent` continues this text
+    fn continues(&self, indent: usize) -> bool {
+        match self.hang {
+            Some(hang) => indent == hang.len(),
+            None if !self.marker.is_empty() => indent == self.indent.len() + self.marker.len(),
+            None if self.item => indent > self.indent.len(),
+            None => indent == self.indent.len(),
+        }
+    }
+}
+
+enum Unit<'a> {
+    /// A line kept as it is
+    Verbatim(&'a str),
+    Text(Text<'a>),
+}
+
+/// Whether `text` starts a reST field, like `:param x:` or `:returns:`
+fn is_field(text: &str) -> bool {
+    text.strip_prefix(':')
+        .and_then(|rest| rest.find(':').map(|end| &rest[..end]))
+        .is_some_and(|name| !name.is_empty() && !name.starts_with(' '))
+}
+
+/// Whether `next` underlines `line`, making it a NumPy style section header
+fn is_underline(line: &str, next: &str) -> bool {
+    let underline = next.trim();
+    underline.len() >= 3
+        && underline.chars().all(|c| c == '-' || c == '=')
+        && line.len() - line.trim_start().len() == next.len() - next.trim_start().len()
+}
+
+/// Split the lines of a docstring into those kept as they are (blank lines,
+/// section headers, doctests, literal blocks) and the text to rewrap
+fn units<'a>(lines: &[&'a str]) -> Vec<Unit<'a>> {
+    let mut units = vec![];
+    let mut section: Option<Section> = None;
+    // lines more indented than this are in a literal block, after a `::`
+    let mut literal: Option<usize> = None;
+    // doctest output runs until a blank line
+    let mut doctest = false;
+    let mut i = 0;
+    while let Some(&line) = lines.get(i) {
+        i += 1;
+        let text = line.trim();
+        let indent = line.len() - line.trim_start().len();
+        if text.is_empty() {
+            doctest = false;
+            units.push(Unit::Verbatim(""));
+            continue;
+        }
+        if section
+            .as_ref()
+            .is_some_and(|s| !s.numpy && indent <= s.indent)
+        {
+            section = None;
+        }
+        if let Some(next) = lines.get(i)
+            && is_underline(line, next)
+        {
+            units.push(Unit::Verbatim(line));
+            units.push(Unit::Verbatim(next));
+            i += 1;
+            section = Some(Section {
+                indent,
+                numpy: true,
+                items: NUMPY_ITEM_SECTIONS.contains(&text),
+                verbatim: EXAMPLE_SECTIONS.contains(&text),
+                item_indent: None,
+            });
+            literal = None;
+            continue;
+        }
+        if let Some(name) = text.strip_suffix(':')
+            && GOOGLE_SECTIONS.contains(&name)
+        {
+            units.push(Unit::Verbatim(line));
+            section = Some(Section {
+                indent,
+                numpy: false,
+                items: GOOGLE_ITEM_SECTIONS.contains(&name),
+                verbatim: EXAMPLE_SECTIONS.contains(&name),
+                item_indent: None,
+            });
+            literal = None;
+            continue;
+        }
+        if literal.is_some_and(|base| indent > base)
+            || doctest
+            || text.starts_with(">>>")
+            || section.as_ref().is_some_and(|s| s.verbatim)
+        {
+            doctest |= text.starts_with(">>>");
+            units.push(Unit::Verbatim(line));
+            continue;
+        }
+        literal = None;
+        if section
+            .as_ref()
+            .is_some_and(|s| s.numpy && s.items && indent == s.indent)
+        {
+            // a `name : type` line
+            units.push(Unit::Verbatim(line));
+            continue;
+        }
+
+        let item = is_field(text)
+            || section.as_mut().is_some_and(|s| {
+                s.items && !s.numpy && indent == *s.item_indent.get_or_insert(indent)
+            });
+        let marker = list_marker(line).map(|len| &line[indent..len]);
+        match units.last_mut() {
+            Some(Unit::Text(t)) if !item && marker.is_none() && t.continues(indent) => {
+                t.hang.get_or_insert(&line[..indent]);
+                extend_words(&mut t.words, text);
+            }
+            Some(Unit::Text(t))
+                if !item
+                    && marker.is_none()
+                    && !t.item
+                    && t.marker.is_empty()
+                    && indent > t.indent.len() =>
+            {
+                // more indented than the paragraph above, like a usage block
+                // or the body of a definition, so it's kept as it is
+                literal = Some(t.indent.len());
+                units.push(Unit::Verbatim(line));
+            }
+            _ => {
+                let marker = marker.unwrap_or("");
+                units.push(Unit::Text(Text {
+                    indent: &line[..indent],
+                    marker,
+                    item,
+                    hang: None,
+                    words: line[indent + marker.len()..]
+                        .split_whitespace()
+                        .map(Token::Borrowed)
+                        .collect(),
+                }));
+            }
+        }
+        if text.ends_with("::")
+            && let Some(Unit::Text(t)) = units.last()
+        {
+            literal = Some(t.indent.len());
+        }
+    }
+    units
+}
+
+/// Rewrap the body of a docstring: the lines between its opening and closing
+/// quotes, without their common indentation. Entries in Google, NumPy and
+/// reST field list sections each wrap with a hanging indent; section headers,
+/// doctests and literal blocks are kept as they are.
+pub(crate) fn reformat(opts: &FormatOpts, body: &str) -> Result<String, Error> {
+    let lines: Vec<&str> = body.lines().collect();
+    let mut output: Vec<String> = vec![];
+    for unit in units(&lines) {
+        let text = match unit {
+            Unit::Verbatim(line) => {
+                output.push(line.trim_end().to_string());
+                continue;
+            }
+            Unit::Text(text) => text,
+        };
+        let item_hang = format!("{}    ", text.indent);
+        let hang = match text.hang {
+            Some(hang) => hang,
+            None if text.item => &item_hang,
+            None => text.indent,
+        };
+        // continuation lines get the block's prefix; the first line gets its
+        // own indentation back afterwards
+        let prefix = if text.marker.is_empty() {
+            hang
+        } else {
+            text.indent
+        };
+        let block = Block {
+            prefix,
+            suffix: "",
+            marker: text.marker,
+            words: text.words,
+            newline_after: false,
+        };
+        let (reformatted, diagnostics) = reformat::with_diagnostics(opts, |opts| {
+            Reformatter::with_blocks(opts, vec![block]).try_reformatted()
+        });
+        reformat::forward(opts, diagnostics, output.len());
+        for (n, line) in reformatted?.split('\n').enumerate() {
+            output.push(match line.strip_prefix(prefix) {
+                Some(rest) if n == 0 => format!("{}{}", text.indent, rest),
+                _ => line.to_string(),
+            });
+        }
+    }
+    Ok(output.join("\n"))
+}
@@ -0,0 +1,100 @@
+use std::fmt;
+
+/// Why text couldn't be reformatted
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The options can't be used, for the reason given
+    InvalidOptions(&'static str),
+    /// No way of breaking a paragraph into lines was found
+    NoSolution,
+    /// A word is wider than the target width, and `LongWordPolicy::Fail` is
+    /// in effect
+    LongWord {
+        word: String,
+        width: usize,
+        target: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidOptions(reason) => write!(f, "invalid options: {}", reason),
+            Error::NoSolution => write!(f, "unable to find line breaks"),
+            Error::LongWord {
+                word,
+                width,
+                target,
+            } => write!(
+                f,
+                "`{}` is {} columns wide, more than the {} available",
+                word, width, target
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Something worth knowing about the output that doesn't stop it from being
+/// produced, sent to `FormatOpts::diagnostics`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+    /// A word too wide for the target width, left to extend beyond it
+    Overflow {
+        /// The line of the output it's on, counting from 1
+        line: usize,
+        word: String,
+        /// Display width of the line's text, not counting any prefix or suffix
+        width: usize,
+        /// The width that was available for the text
+        target: usize,
+    },
+    /// A commit message's subject line wider than is recommended
+    LongSubject {
+        line: usize,
+        width: usize,
+        limit: usize,
+    },
+}
+
+impl Diagnostic {
+    /// The same diagnostic, `lines` lines further down
+    pub(crate) fn offset(self, lines: usize) -> Self {
+        match self {
+            Diagnostic::Overflow {
+                line,
+                word,
+                width,
+                target,
+            } => Diagnostic::Overflow {
+                line: line + lines,
+                word,
+                width,
+                target,
+            },
+            Diagnostic::LongSubject { line, width, limit } => Diagnostic::LongSubject {
+                line: line + lines,
+                width,
+                limit,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Overflow { line, word, .. } => write!(
+                f,
+                "line {}: allowing `{}` to extend beyond the target width",
+                line, word
+            ),
+            Diagnostic::LongSubject { line, width, limit } => write!(
+                f,
+                "line {}: the subject line is {} columns wide, more than the recommended {}",
+                line, width, limit
+            ),
+        }
+    }
+}
@@ -13,35 +13,533 @@
 //! ```
 
 mod analysis;
+mod ansi;
+mod breaks;
+mod commit;
+mod docstring;
+mod error;
+mod markdown;
 pub mod reformat;
+mod source;
 
-pub use reformat::{FormatMode, FormatOpts, Reformatter, reformat};
+pub use error::{Diagnostic, Error};
+pub use reformat::{
+    Alignment, CostModel, FormatMode, FormatOpts, LastLine, LineEnding, LongWordPolicy,
+    Reformatter, Solver, reformat, try_reformat,
+};
+pub use source::{LANGUAGES, Language};
 
 use std::io::{self, BufRead, Write};
+use std::ops::{Range, RangeInclusive};
 
-fn print_reformatted<W: Write>(out: &mut W, opts: &FormatOpts, buf: &[String]) -> io::Result<()> {
-    if !buf.is_empty() {
-        writeln!(out, "{}", reformat(opts, &buf.join("\n")))?;
+/// A run of input lines (a paragraph, a single blank line, or in markdown mode
+/// a stretch of non-paragraph text) along with the lines that reformatting
+/// produces for it
+pub struct Paragraph {
+    /// One-based line number of the first original line
+    pub line: usize,
+    pub original: Vec<String>,
+    pub reformatted: Vec<String>,
+    /// Line ending of the first original line
+    pub line_ending: LineEnding,
+    /// Whether the last original line has a line ending (only the last line
+    /// of the input can be without one)
+    pub newline_at_end: bool,
+    /// Diagnostics for the reformatted lines, numbered from 1 at the first
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Paragraph {
+    fn new(line: usize, original: Vec<String>, reformatted: &str) -> Self {
+        Paragraph {
+            line,
+            original,
+            reformatted: reformatted.split('\n').map(String::from).collect(),
+            line_ending: LineEnding::Lf,
+            newline_at_end: true,
+            diagnostics: vec![],
+        }
+    }
+
+    fn unchanged(line: usize, original: Vec<String>) -> Self {
+        Paragraph {
+            line,
+            reformatted: original.clone(),
+            original,
+            line_ending: LineEnding::Lf,
+            newline_at_end: true,
+            diagnostics: vec![],
+        }
+    }
+
+    fn with_endings(self, line_ending: LineEnding, newline_at_end: bool) -> Self {
+        Paragraph {
+            line_ending,
+            newline_at_end,
+            ..self
+        }
+    }
+
+    /// Take the line endings from the original text, `input[range]`. A last
+    /// line without an ending gets the one of the line before it.
+    fn with_endings_of(self, input: &str, range: Range<usize>) -> Self {
+        let raw = &input[range.clone()];
+        let first = match raw.find('\n') {
+            Some(i) => &raw[..=i],
+            None => &input[..range.start],
+        };
+        let line_ending = if first.ends_with("\r\n") {
+            LineEnding::CrLf
+        } else {
+            LineEnding::Lf
+        };
+        self.with_endings(line_ending, raw.ends_with('\n'))
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.original != self.reformatted
+    }
+
+    /// Number of lines at the start and at the end that reformatting leaves alone
+    fn unchanged_ends(&self) -> (usize, usize) {
+        let leading = self
+            .original
+            .iter()
+            .zip(self.reformatted.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let max_trailing = self.original.len().min(self.reformatted.len()) - leading;
+        let trailing = self
+            .original
+            .iter()
+            .rev()
+            .zip(self.reformatted.iter().rev())
+            .take(max_trailing)
+            .take_while(|(a, b)| a == b)
+            .count();
+        (leading, trailing)
+    }
+
+    /// One-based, inclusive range of original lines that reformatting changes,
+    /// leaving out any unchanged lines at the start and end of the paragraph
+    pub fn changed_lines(&self) -> Option<RangeInclusive<usize>> {
+        if !self.is_changed() {
+            return None;
+        }
+        let (leading, trailing) = self.unchanged_ends();
+        let start = self.line + leading;
+        let end = (self.line + self.original.len()).saturating_sub(trailing + 1);
+        Some(start..=end.max(start))
+    }
+}
+
+/// Apply `f` to each item, on a thread pool if the `parallel` feature is
+/// enabled, keeping the results in order
+pub(crate) fn map_in_order<T, U, F>(items: Vec<T>, f: F) -> Vec<U>
+where
+    T: Send,
+    U: Send,
+    F: Fn(T) -> U + Sync + Send,
+{
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        items.into_par_iter().map(f).collect()
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        items.into_iter().map(f).collect()
+    }
+}
+
+/// Number of paragraphs (and blank lines) read before reformatting them
+/// together, so that they can be spread over threads
+#[cfg(feature = "parallel")]
+const BATCH_SIZE: usize = 256;
+#[cfg(not(feature = "parallel"))]
+const BATCH_SIZE: usize = 1;
+
+/// Reformat a batch of paragraphs and blank lines and pass them to `f` in
+/// order
+fn flush_batch<F>(opts: &FormatOpts, batch: Vec<Paragraph>, f: &mut F) -> io::Result<()>
+where
+    F: FnMut(Paragraph) -> io::Result<()>,
+{
+    let paragraphs = map_in_order(batch, |para| {
+        if para.original.iter().all(|l| l.trim().is_empty()) {
+            return Ok::<_, Error>(para);
+        }
+        let input = para.original.join("\n");
+        let (reformatted, diagnostics) =
+            reformat::with_diagnostics(opts, |opts| reformat::reformat_unchecked(opts, &input));
+        Ok(Paragraph {
+            reformatted: reformatted?.split('\n').map(String::from).collect(),
+            diagnostics,
+            ..para
+        })
+    });
+    for para in paragraphs {
+        f(para.map_err(io::Error::other)?)?;
     }
     Ok(())
 }
 
+/// Read lines like `BufRead::lines`, each along with its line ending (`None`
+/// for a last line without one)
+fn read_lines<R: BufRead + ?Sized>(
+    reader: &mut R,
+) -> impl Iterator<Item = io::Result<(String, Option<LineEnding>)>> + '_ {
+    std::iter::from_fn(move || {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => {
+                let ending = if line.ends_with("\r\n") {
+                    line.truncate(line.len() - 2);
+                    Some(LineEnding::CrLf)
+                } else if line.ends_with('\n') {
+                    line.pop();
+                    Some(LineEnding::Lf)
+                } else {
+                    None
+                };
+                Some(Ok((line, ending)))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Write `lines` with the given line ending after each, except after the last
+/// one unless `newline_at_end` is set
+fn write_lines<W: Write, S: AsRef<str>>(
+    out: &mut W,
+    lines: &[S],
+    ending: LineEnding,
+    newline_at_end: bool,
+) -> io::Result<()> {
+    for (i, line) in lines.iter().enumerate() {
+        out.write_all(line.as_ref().as_bytes())?;
+        if i + 1 < lines.len() || newline_at_end {
+            out.write_all(ending.as_str().as_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+/// Split the input into paragraphs the same way `process_paragraphs` does,
+/// and call `f` with each one (including blank lines) in order.
+pub fn for_each_paragraph<R, F>(reader: &mut R, opts: &FormatOpts, mut f: F) -> io::Result<()>
+where
+    R: BufRead + ?Sized,
+    F: FnMut(Paragraph) -> io::Result<()>,
+{
+    if opts.format_mode.whole_document() {
+        // Markdown blocks (e.g. fenced code), source code and commit message
+        // trailers depend on what's around them, so the document has to be
+        // handled as a whole
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let lines = |s: &str| s.lines().map(String::from).collect::<Vec<_>>();
+        let mut pos = 0;
+        let mut line = 1;
+        let rewrapped = reformat::rewrapped(opts, &input).map_err(io::Error::other)?;
+        for reformat::Rewrapped {
+            range,
+            text,
+            diagnostics,
+        } in rewrapped
+        {
+            if pos < range.start {
+                let between = lines(&input[pos..range.start]);
+                let count = between.len();
+                f(Paragraph::unchanged(line, between).with_endings_of(&input, pos..range.start))?;
+                line += count;
+            }
+            let original = lines(&input[range.clone()]);
+            let count = original.len();
+            // skip the line ending
+            pos = input[range.end..]
+                .find('\n')
+                .map(|i| range.end + i + 1)
+                .unwrap_or(input.len());
+            let para = Paragraph {
+                diagnostics,
+                ..Paragraph::new(line, original, &text)
+            };
+            f(para.with_endings_of(&input, range.start..pos))?;
+            line += count;
+        }
+        if pos < input.len() {
+            let para = Paragraph::unchanged(line, lines(&input[pos..]));
+            f(para.with_endings_of(&input, pos..input.len()))?;
+        }
+        return Ok(());
+    }
+    let mut batch = vec![];
+    let mut buf = vec![];
+    let mut start = 1;
+    // line ending of the first line in `buf`, and of the line read last; a
+    // last line without one is taken to have the same as the line before
+    let mut first_ending = LineEnding::Lf;
+    let mut last_ending = LineEnding::Lf;
+    let mut newline_at_end = true;
+    for (i, line) in read_lines(reader).enumerate() {
+        let (l, ending) = line?;
+        newline_at_end = ending.is_some();
+        last_ending = ending.unwrap_or(last_ending);
+        if l.trim().is_empty() {
+            if !buf.is_empty() {
+                let para = Paragraph::unchanged(start, std::mem::take(&mut buf));
+                batch.push(para.with_endings(first_ending, true));
+            }
+            let blank = Paragraph::new(i + 1, vec![l], "");
+            batch.push(blank.with_endings(last_ending, newline_at_end));
+            start = i + 2;
+            if batch.len() >= BATCH_SIZE {
+                flush_batch(opts, std::mem::take(&mut batch), &mut f)?;
+            }
+        } else {
+            if buf.is_empty() {
+                first_ending = last_ending;
+            }
+            buf.push(l);
+        }
+    }
+    if !buf.is_empty() {
+        let para = Paragraph::unchanged(start, buf);
+        batch.push(para.with_endings(first_ending, newline_at_end));
+    }
+    flush_batch(opts, batch, &mut f)
+}
+
 pub fn process_paragraphs<R: BufRead + ?Sized, W: Write>(
     reader: &mut R,
     out: &mut W,
     opts: FormatOpts,
 ) -> io::Result<()> {
+    let mut written = 0;
+    for_each_paragraph(reader, &opts, |para| {
+        reformat::forward(&opts, para.diagnostics, written);
+        written += para.reformatted.len();
+        let ending = opts.line_ending.unwrap_or(para.line_ending);
+        write_lines(out, &para.reformatted, ending, para.newline_at_end)
+    })
+}
+
+/// Lines held back at the end of each chunk while streaming, to be reformatted
+/// again along with the next chunk
+const STREAM_LOOKAHEAD: usize = 4;
+
+/// Streamed output, and the number of lines written to it so far
+struct StreamOut<'a, W> {
+    out: &'a mut W,
+    lines: usize,
+}
+
+fn write_chunk<W: Write>(
+    out: &mut StreamOut<W>,
+    opts: &FormatOpts,
+    lines: &mut Vec<String>,
+    carry: Option<reformat::Carry>,
+    keep: usize,
+    newline_at_end: bool,
+) -> io::Result<Option<reformat::Carry>> {
+    let input = lines.join("\n");
+    let (result, diagnostics) = reformat::with_diagnostics(opts, |opts| {
+        reformat::reformat_chunk(opts, &input, carry, keep)
+    });
+    let (text, carry) = result.map_err(io::Error::other)?;
+    lines.clear();
+    reformat::forward(opts, diagnostics, out.lines);
+    if !text.is_empty() {
+        let reformatted: Vec<&str> = text.split('\n').collect();
+        let ending = opts.line_ending.unwrap_or_default();
+        write_lines(out.out, &reformatted, ending, newline_at_end)?;
+        out.lines += reformatted.len();
+    }
+    Ok(carry)
+}
+
+/// Like `process_paragraphs`, but in bounded memory: a paragraph longer than
+/// `window` lines (at least 8) is reformatted a chunk at a time, and lines are written as
+/// soon as they're final. The last few lines of each chunk are held back and
+/// reformatted along with the next one, which usually gives the same result;
+/// the prefix and suffix of a long paragraph are the ones found in its first
+/// chunk. Lines are all written with the line ending of the first one.
+/// Markdown, source files and commit messages have to be read as a whole, so
+/// they aren't streamed, and neither are code comments: a block comment split
+/// into chunks loses its delimiters, and doc comments are read as Markdown.
+pub fn stream_paragraphs<R: BufRead + ?Sized, W: Write>(
+    reader: &mut R,
+    out: &mut W,
+    mut opts: FormatOpts,
+    window: usize,
+) -> io::Result<()> {
+    if opts.format_mode.whole_document() || matches!(opts.format_mode, FormatMode::Code) {
+        return process_paragraphs(reader, out, opts);
+    }
+    // analysing the prefix and suffix needs a few lines to go on
+    let window = window.max(2 * STREAM_LOOKAHEAD);
+    let out = &mut StreamOut { out, lines: 0 };
     let mut buf = vec![];
-    for line in reader.lines() {
-        let l = line?;
+    let mut carry = None;
+    let mut newline_at_end = true;
+    for line in read_lines(reader) {
+        let (l, ending) = line?;
+        newline_at_end = ending.is_some();
+        let ending = *opts.line_ending.get_or_insert(ending.unwrap_or_default());
         if l.trim().is_empty() {
-            print_reformatted(out, &opts, &buf)?;
-            writeln!(out)?;
-            buf = vec![];
+            if !buf.is_empty() || carry.is_some() {
+                write_chunk(out, &opts, &mut buf, carry.take(), 0, true)?;
+            }
+            if newline_at_end {
+                out.out.write_all(ending.as_str().as_bytes())?;
+            }
+            out.lines += 1;
         } else {
             buf.push(l);
+            if buf.len() >= window {
+                carry = write_chunk(out, &opts, &mut buf, carry.take(), STREAM_LOOKAHEAD, true)?;
+            }
         }
     }
-    print_reformatted(out, &opts, &buf)?;
+    if !buf.is_empty() || carry.is_some() {
+        write_chunk(out, &opts, &mut buf, carry, 0, newline_at_end)?;
+    }
     Ok(())
 }
+
+/// Like `process_paragraphs`, but only reformat the paragraphs that overlap
+/// `lines` (one-based and inclusive). Every other line is written out exactly
+/// as it was read, line ending included, unless `opts.line_ending` is set.
+pub fn process_paragraphs_in_range<R: BufRead + ?Sized, W: Write>(
+    reader: &mut R,
+    out: &mut W,
+    opts: FormatOpts,
+    lines: RangeInclusive<usize>,
+) -> io::Result<()> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    let raw: Vec<&str> = input.split_inclusive('\n').collect();
+
+    let mut written = 0;
+    for_each_paragraph(&mut input.as_bytes(), &opts, |para| {
+        let first = para.line - 1;
+        let last = first + para.original.len();
+        let overlaps = para.line <= *lines.end() && last >= *lines.start();
+        let ending = opts.line_ending.unwrap_or(para.line_ending);
+        if !overlaps || !para.is_changed() {
+            written += para.original.len();
+            if opts.line_ending.is_some() {
+                return write_lines(out, &para.original, ending, para.newline_at_end);
+            }
+            for line in &raw[first..last] {
+                out.write_all(line.as_bytes())?;
+            }
+            return Ok(());
+        }
+        reformat::forward(&opts, para.diagnostics, written);
+        written += para.reformatted.len();
+        write_lines(out, &para.reformatted, ending, para.newline_at_end)
+    })
+}
+
+/// Line ranges (one-based and inclusive) that `process_paragraphs` would
+/// change. An empty result means the input is already formatted.
+pub fn check_paragraphs<R: BufRead + ?Sized>(
+    reader: &mut R,
+    opts: FormatOpts,
+) -> io::Result<Vec<RangeInclusive<usize>>> {
+    let mut changes = vec![];
+    for_each_paragraph(reader, &opts, |para| {
+        changes.extend(para.changed_lines());
+        Ok(())
+    })?;
+    Ok(changes)
+}
+
+/// Write one line of a diff hunk with its line ending, so that the diff
+/// applies to the file as it is. A line without one is marked the way `patch`
+/// expects.
+fn write_hunk_line<W: Write>(
+    out: &mut W,
+    tag: char,
+    line: &str,
+    ending: Option<LineEnding>,
+) -> io::Result<()> {
+    match ending {
+        Some(ending) => write!(out, "{}{}{}", tag, line, ending.as_str()),
+        None => writeln!(out, "{}{}\n\\ No newline at end of file", tag, line),
+    }
+}
+
+/// Write a unified diff between the input and its reformatted output, with one
+/// hunk per changed paragraph. Returns whether there were any changes.
+pub fn diff_paragraphs<R: BufRead + ?Sized, W: Write>(
+    reader: &mut R,
+    out: &mut W,
+    opts: FormatOpts,
+    name: &str,
+) -> io::Result<bool> {
+    let mut changed = false;
+    let mut offset: isize = 0;
+    for_each_paragraph(reader, &opts, |para| {
+        let old_len = para.original.len();
+        let new_len = para.reformatted.len();
+        let new_line = para.line as isize + offset;
+        offset += new_len as isize - old_len as isize;
+        if !para.is_changed() {
+            return Ok(());
+        }
+        if !changed {
+            writeln!(out, "--- {}", name)?;
+            writeln!(out, "+++ {}", name)?;
+            changed = true;
+        }
+        let (leading, trailing) = para.unchanged_ends();
+        writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            para.line, old_len, new_line, new_len
+        )?;
+        // only the last line of the input can lack a line ending
+        let old_last = old_len - 1;
+        let new_last = new_len - 1;
+        let old_ending = para.line_ending;
+        let new_ending = opts.line_ending.unwrap_or(para.line_ending);
+        let ends =
+            |ending: LineEnding, last: bool| (!last || para.newline_at_end).then_some(ending);
+        for (k, line) in para.original[..leading].iter().enumerate() {
+            write_hunk_line(
+                out,
+                ' ',
+                line,
+                ends(old_ending, k == old_last && k == new_last),
+            )?;
+        }
+        for (k, line) in para
+            .original
+            .iter()
+            .enumerate()
+            .take(old_len - trailing)
+            .skip(leading)
+        {
+            write_hunk_line(out, '-', line, ends(old_ending, k == old_last))?;
+        }
+        for (k, line) in para
+            .reformatted
+            .iter()
+            .enumerate()
+            .take(new_len - trailing)
+            .skip(leading)
+        {
+            write_hunk_line(out, '+', line, ends(new_ending, k == new_last))?;
+        }
+        for (k, line) in para.original.iter().enumerate().skip(old_len - trailing) {
+            write_hunk_line(out, ' ', line, ends(old_ending, k == old_last))?;
+        }
+        Ok(())
+    })?;
+    Ok(changed)
+}
@@ -1,9 +1,16 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 
 use std::fs;
-use std::io::{self, BufRead, BufReader};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 
-use prose::{FormatMode, FormatOpts, process_paragraphs};
+use prose::{
+    Alignment, FormatMode, FormatOpts, LANGUAGES, Language, LineEnding, LongWordPolicy, Solver,
+    check_paragraphs, diff_paragraphs, process_paragraphs, process_paragraphs_in_range,
+    stream_paragraphs,
+};
 
 fn get_reader(input: &str) -> io::Result<Box<dyn BufRead>> {
     if input == "-" {
@@ -13,11 +20,122 @@ fn get_reader(input: &str) -> io::Result<Box<dyn BufRead>> {
     }
 }
 
+fn parse_line_range(s: &str) -> Result<RangeInclusive<usize>, String> {
+    let (start, end) = s
+        .split_once(':')
+        .ok_or_else(|| String::from("expected START:END"))?;
+    let start: usize = start.parse().map_err(|e| format!("invalid START: {}", e))?;
+    let end: usize = end.parse().map_err(|e| format!("invalid END: {}", e))?;
+    if start == 0 || end < start {
+        return Err(String::from("expected 1 <= START <= END"));
+    }
+    Ok(start..=end)
+}
+
+fn parse_language(s: &str) -> Result<&'static Language, String> {
+    Language::named(s).ok_or_else(|| {
+        let names: Vec<_> = LANGUAGES.iter().map(Language::name).collect();
+        format!("expected one of {}", names.join(", "))
+    })
+}
+
+fn format<R: BufRead + ?Sized, W: Write>(
+    rdr: &mut R,
+    out: &mut W,
+    opts: &FormatOpts,
+    lines: Option<&RangeInclusive<usize>>,
+    stream: Option<usize>,
+) -> io::Result<()> {
+    match (lines, stream) {
+        (Some(range), _) => process_paragraphs_in_range(rdr, out, opts.clone(), range.clone()),
+        (None, Some(window)) => stream_paragraphs(rdr, out, opts.clone(), window),
+        (None, None) => process_paragraphs(rdr, out, opts.clone()),
+    }
+}
+
+fn display_name(input: &str) -> &str {
+    if input == "-" { "<stdin>" } else { input }
+}
+
+/// Path of the temporary file used while rewriting `path`, in the same
+/// directory so that it can be renamed over the original
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.prose-{}", name, std::process::id()))
+}
+
+fn write_in_place(
+    path: &Path,
+    opts: &FormatOpts,
+    lines: Option<&RangeInclusive<usize>>,
+    stream: Option<usize>,
+    backup_suffix: Option<&str>,
+) -> io::Result<()> {
+    // a symlink is followed, so that it still points at the rewritten file
+    let target = fs::canonicalize(path)?;
+    let permissions = fs::metadata(&target)?.permissions();
+    let mut rdr = BufReader::new(fs::File::open(&target)?);
+    let tmp = temp_path(&target);
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp)?;
+
+    let result = (|| {
+        let mut out = BufWriter::new(file);
+        format(&mut rdr, &mut out, opts, lines, stream)?;
+        let file = out.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::set_permissions(&tmp, permissions)?;
+        if let Some(suffix) = backup_suffix {
+            let mut backup = path.as_os_str().to_owned();
+            backup.push(suffix);
+            fs::copy(path, backup)?;
+        }
+        fs::rename(&tmp, &target)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LineBreaker {
+    ShortestPath,
+    Dp,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LongWords {
+    Allow,
+    Break,
+    BreakAtUrlPoints,
+    Error,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Eol {
+    Lf,
+    Crlf,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// Filename or "-" for stdin
-    file: Option<String>,
+    /// Filenames or "-" for stdin
+    files: Vec<String>,
 
     /// Target width
     #[arg(short, long, default_value_t = 72)]
@@ -42,41 +160,211 @@ struct Cli {
     /// Try to handle code comments
     #[arg(short, long)]
     code_comments: bool,
+
+    /// Treat inputs as source files, rewrapping only their comments
+    #[arg(long, conflicts_with_all = ["markdown", "code_comments"])]
+    code: bool,
+
+    /// Treat inputs as git commit messages, leaving the subject line, trailers
+    /// and comments alone
+    #[arg(long, conflicts_with_all = ["markdown", "code_comments", "code"])]
+    git_commit: bool,
+
+    /// Language of the source files, instead of going by their extensions
+    #[arg(long, value_name = "NAME", value_parser = parse_language, requires = "code")]
+    lang: Option<&'static Language>,
+
+    /// Pad lines with extra spaces between words to fill the width exactly
+    #[arg(short, long)]
+    justify: bool,
+
+    /// Break long words with a hyphen where English hyphenation rules allow
+    #[cfg(feature = "hyphenation")]
+    #[arg(long)]
+    hyphenate: bool,
+
+    /// Ignore terminal escape sequences (e.g. colors) when measuring line widths
+    #[arg(long)]
+    ansi: bool,
+
+    /// Algorithm for choosing line breaks; dp (dynamic programming) is faster on long paragraphs
+    #[arg(long, value_enum, default_value_t = LineBreaker::ShortestPath)]
+    solver: LineBreaker,
+
+    /// What to do with words wider than the target width: let them stick out,
+    /// break them anywhere, break them after `/`, `?`, `&` or `-`, or fail
+    #[arg(long, value_enum, default_value_t = LongWords::Allow)]
+    long_words: LongWords,
+
+    /// Alignment of each line between its prefix and suffix
+    #[arg(short, long, value_enum, default_value_t = Align::Left)]
+    align: Align,
+
+    /// Line ending to write, instead of keeping the input's
+    #[arg(long, value_enum)]
+    line_ending: Option<Eol>,
+
+    /// Rewrite files in place instead of printing to stdout
+    #[arg(short, long)]
+    in_place: bool,
+
+    /// Keep a copy of each original file, with this suffix appended to its name
+    #[arg(long, value_name = "SUFFIX", requires = "in_place")]
+    backup_suffix: Option<String>,
+
+    /// Don't write anything; exit with status 1 if any input would be reformatted
+    #[arg(long, conflicts_with = "in_place")]
+    check: bool,
+
+    /// Print a unified diff of the changes instead of the reformatted text
+    #[arg(long, conflicts_with_all = ["in_place", "check"])]
+    diff: bool,
+
+    /// Only reformat the paragraphs touching this range of lines (e.g. 10:20)
+    #[arg(long, value_name = "START:END", value_parser = parse_line_range, conflicts_with_all = ["check", "diff"])]
+    lines: Option<RangeInclusive<usize>>,
+
+    /// Reformat long paragraphs this many lines at a time, writing output as it goes
+    #[arg(long, value_name = "LINES", conflicts_with_all = ["check", "diff", "lines"])]
+    stream: Option<usize>,
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let input = cli.file.unwrap_or_else(|| String::from("-"));
+    let mut inputs = cli.files;
+    if inputs.is_empty() {
+        inputs.push(String::from("-"));
+    }
 
     let format_mode = if cli.markdown {
         FormatMode::Markdown
+    } else if cli.git_commit {
+        FormatMode::GitCommit
     } else if cli.code_comments {
         FormatMode::Code
     } else {
         FormatMode::PlainText
     };
 
+    let (sender, diagnostics) = mpsc::channel();
     let opts = FormatOpts {
         max_length: cli.width,
         last_line: cli.last_line,
         reduce_jaggedness: cli.fit,
         tab_width: cli.tab_width,
         format_mode,
+        justify: cli.justify,
+        ansi: cli.ansi,
+        #[cfg(feature = "hyphenation")]
+        hyphenate: cli.hyphenate,
+        alignment: match cli.align {
+            Align::Left => Alignment::Left,
+            Align::Right => Alignment::Right,
+            Align::Center => Alignment::Center,
+        },
+        solver: match cli.solver {
+            LineBreaker::ShortestPath => Solver::ShortestPath,
+            LineBreaker::Dp => Solver::DynamicProgramming,
+        },
+        long_words: match cli.long_words {
+            LongWords::Allow => LongWordPolicy::Allow,
+            LongWords::Break => LongWordPolicy::Break,
+            LongWords::BreakAtUrlPoints => LongWordPolicy::BreakAtUrlPoints,
+            LongWords::Error => LongWordPolicy::Fail,
+        },
+        line_ending: cli.line_ending.map(|eol| match eol {
+            Eol::Lf => LineEnding::Lf,
+            Eol::Crlf => LineEnding::CrLf,
+        }),
+        diagnostics: Some(sender),
+        ..Default::default()
     };
 
-    match get_reader(&input) {
-        Ok(mut rdr) => {
-            let stdout = io::stdout();
-            let mut out = stdout.lock();
-            if let Err(err) = process_paragraphs(&mut rdr, &mut out, opts) {
-                eprintln!("{}", err);
-                ::std::process::exit(2);
+    let mut status = 0;
+
+    for input in &inputs {
+        let mut opts = opts.clone();
+        if cli.code {
+            match cli.lang.or_else(|| Language::for_path(Path::new(input))) {
+                Some(lang) => opts.format_mode = FormatMode::Source(lang),
+                None => {
+                    eprintln!(
+                        "Unknown language for {}; use --lang to give one",
+                        display_name(input)
+                    );
+                    status = status.max(1);
+                    continue;
+                }
             }
         }
-        Err(e) => {
-            eprintln!("Error opening {}: {}", input, e);
-            ::std::process::exit(1);
+
+        if cli.in_place {
+            if input == "-" {
+                eprintln!("Cannot rewrite stdin in place");
+                status = status.max(1);
+            } else if let Err(e) = write_in_place(
+                Path::new(input),
+                &opts,
+                cli.lines.as_ref(),
+                cli.stream,
+                cli.backup_suffix.as_deref(),
+            ) {
+                eprintln!("Error rewriting {}: {}", input, e);
+                status = status.max(1);
+            }
+        } else {
+            match get_reader(input) {
+                Ok(mut rdr) if cli.diff => {
+                    let stdout = io::stdout();
+                    let mut out = stdout.lock();
+                    if let Err(err) =
+                        diff_paragraphs(&mut rdr, &mut out, opts.clone(), display_name(input))
+                    {
+                        eprintln!("{}: {}", input, err);
+                        status = status.max(2);
+                    }
+                }
+                Ok(mut rdr) if cli.check => match check_paragraphs(&mut rdr, opts.clone()) {
+                    Ok(changes) => {
+                        let name = display_name(input);
+                        for range in &changes {
+                            if range.start() == range.end() {
+                                println!("{}: line {}", name, range.start());
+                            } else {
+                                println!("{}: lines {}-{}", name, range.start(), range.end());
+                            }
+                        }
+                        if !changes.is_empty() {
+                            status = status.max(1);
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("{}: {}", input, err);
+                        status = status.max(2);
+                    }
+                },
+                Ok(mut rdr) => {
+                    let stdout = io::stdout();
+                    let mut out = stdout.lock();
+                    if let Err(err) =
+                        format(&mut rdr, &mut out, &opts, cli.lines.as_ref(), cli.stream)
+                    {
+                        eprintln!("{}", err);
+                        ::std::process::exit(2);
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error opening {}: {}", input, e);
+                    status = status.max(1);
+                }
+            }
+        }
+
+        for diagnostic in diagnostics.try_iter() {
+            eprintln!("Warning: {}: {}", display_name(input), diagnostic);
         }
     }
+
+    ::std::process::exit(status);
 }
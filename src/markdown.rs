@@ -0,0 +1,345 @@
+use crate::analysis::{Block, Token, Width, extend_words};
+use crate::error::Error;
+use crate::reformat::{self, FormatMode, FormatOpts, Reformatter, Rewrapped};
+use pulldown_cmark::{Event, Options, Parser, Tag, TagEnd};
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Container {
+    /// A block whose inline content may be rewrapped
+    Paragraph,
+    /// A list item; tight lists put their inline content directly inside it
+    Item,
+    BlockQuote,
+    /// Lists, footnote definitions and other blocks that only hold blocks
+    Other,
+    /// Headings, code, tables, HTML: never touched
+    Verbatim,
+}
+
+fn classify(tag: &Tag) -> Option<Container> {
+    let kind = match tag {
+        Tag::Paragraph => Container::Paragraph,
+        Tag::Item => Container::Item,
+        Tag::BlockQuote(_) => Container::BlockQuote,
+        Tag::List(_) | Tag::FootnoteDefinition(_) | Tag::DefinitionList => Container::Other,
+        Tag::Heading { .. }
+        | Tag::CodeBlock(_)
+        | Tag::HtmlBlock
+        | Tag::Table(_)
+        | Tag::TableHead
+        | Tag::TableRow
+        | Tag::TableCell
+        | Tag::DefinitionListTitle
+        | Tag::DefinitionListDefinition
+        | Tag::MetadataBlock(_) => Container::Verbatim,
+        _ => return None,
+    };
+    Some(kind)
+}
+
+fn is_block_end(tag: &TagEnd) -> bool {
+    !matches!(
+        tag,
+        TagEnd::Emphasis
+            | TagEnd::Strong
+            | TagEnd::Strikethrough
+            | TagEnd::Superscript
+            | TagEnd::Subscript
+            | TagEnd::Link
+            | TagEnd::Image
+    )
+}
+
+/// A run of inline content, in source byte offsets
+#[derive(Default)]
+struct Span {
+    range: Option<Range<usize>>,
+    content_start: Option<usize>,
+    hard_breaks: Vec<usize>,
+}
+
+impl Span {
+    fn extend(&mut self, r: &Range<usize>) {
+        self.range = Some(match self.range.take() {
+            None => r.clone(),
+            Some(prev) => prev.start.min(r.start)..prev.end.max(r.end),
+        });
+    }
+}
+
+/// A paragraph to be rewrapped, along with its surrounding context
+struct Paragraph {
+    range: Range<usize>,
+    content_start: usize,
+    hard_breaks: Vec<usize>,
+    quote_depth: usize,
+    nested: bool,
+}
+
+fn find_paragraphs(input: &str) -> Vec<Paragraph> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+    let mut paragraphs = vec![];
+    let mut stack: Vec<Container> = vec![];
+    let mut span = Span::default();
+
+    let mut flush = |span: &mut Span, stack: &[Container]| {
+        let span = std::mem::take(span);
+        if let Some(range) = span.range {
+            paragraphs.push(Paragraph {
+                content_start: span.content_start.unwrap_or(range.start),
+                range,
+                hard_breaks: span.hard_breaks,
+                quote_depth: stack
+                    .iter()
+                    .filter(|&&c| c == Container::BlockQuote)
+                    .count(),
+                nested: stack.iter().any(|&c| c != Container::Paragraph),
+            });
+        }
+    };
+
+    for (event, range) in Parser::new_ext(input, options).into_offset_iter() {
+        let rewrappable = matches!(
+            stack.last(),
+            Some(Container::Paragraph) | Some(Container::Item)
+        );
+        match event {
+            Event::Start(ref tag) => {
+                if let Some(kind) = classify(tag) {
+                    flush(&mut span, &stack);
+                    stack.push(kind);
+                } else if rewrappable {
+                    span.extend(&range);
+                }
+            }
+            Event::End(ref tag) if is_block_end(tag) => {
+                flush(&mut span, &stack);
+                stack.pop();
+            }
+            Event::End(_) => {}
+            Event::Rule | Event::Html(_) | Event::DisplayMath(_) => {
+                flush(&mut span, &stack);
+            }
+            Event::TaskListMarker(_) if rewrappable && span.range.is_none() => {
+                // keep the checkbox with the list marker
+                let rest = &input[range.end..];
+                let ws = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+                span.content_start = Some(range.end + ws);
+                span.extend(&range);
+            }
+            Event::HardBreak if rewrappable => {
+                span.hard_breaks.push(range.start);
+            }
+            Event::SoftBreak => {}
+            _ if rewrappable => {
+                span.extend(&range);
+            }
+            _ => {}
+        }
+    }
+    flush(&mut span, &stack);
+    paragraphs
+}
+
+/// Derive the prefix for continuation lines from the prefix of the first line,
+/// replacing list markers and footnote labels with spaces of the same width
+fn continuation_prefix(first: &str, tab_width: usize) -> String {
+    let mut prefix = String::with_capacity(first.len());
+    for g in first.graphemes(true) {
+        match g {
+            ">" | " " => prefix.push_str(g),
+            "\t" => prefix.push_str(&" ".repeat(tab_width)),
+            _ => prefix.push_str(&" ".repeat(g.width())),
+        }
+    }
+    prefix
+}
+
+/// Remove blockquote markers (if present) and indentation from a continuation line
+fn strip_quotes(line: &str, depth: usize) -> &str {
+    let mut rest = line;
+    for _ in 0..depth {
+        match rest.trim_start().strip_prefix('>') {
+            Some(r) => rest = r,
+            None => break,
+        }
+    }
+    rest.trim_start()
+}
+
+fn line_start(input: &str, idx: usize) -> usize {
+    input[..idx].rfind('\n').map(|i| i + 1).unwrap_or(0)
+}
+
+fn line_end(input: &str, idx: usize) -> usize {
+    let end = input[idx..]
+        .find('\n')
+        .map(|i| idx + i)
+        .unwrap_or(input.len());
+    if input[..end].ends_with('\r') {
+        end - 1
+    } else {
+        end
+    }
+}
+
+fn reformat_paragraph(opts: &FormatOpts, input: &str, para: &Paragraph) -> Result<String, Error> {
+    let start = line_start(input, para.range.start);
+    let end = line_end(input, para.range.end);
+    let first_prefix = &input[start..para.content_start];
+    let prefix = if para.nested {
+        continuation_prefix(first_prefix, opts.tab_width)
+    } else {
+        String::new()
+    };
+
+    // Split into segments at hard line breaks, which must be kept
+    let mut segments: Vec<(Vec<Token>, &str)> = vec![];
+    let mut words: Vec<Token> = vec![];
+    let mut offset = start;
+    for (i, raw) in input[start..end].split('\n').enumerate() {
+        let line = raw.strip_suffix('\r').unwrap_or(raw);
+        let line_range = offset..(offset + line.len());
+        offset += raw.len() + 1;
+        let content = if i == 0 {
+            if para.nested {
+                &input[para.content_start..line_range.end]
+            } else {
+                // leave the indentation attached to the first word
+                line
+            }
+        } else {
+            strip_quotes(line, para.quote_depth)
+        };
+        if i == 0 && !para.nested {
+            let indentation = content.len() - content.trim_start().len();
+            let mut iter = content.split_whitespace();
+            if let Some(first_word) = iter.next() {
+                words.push(Token::Borrowed(
+                    &content[0..(first_word.len() + indentation)],
+                ));
+            }
+            words.extend(iter.map(Token::Borrowed));
+        } else {
+            extend_words(&mut words, content);
+        }
+        if para.hard_breaks.iter().any(|b| line_range.contains(b)) {
+            let trailing = &line[line.trim_end().len()..];
+            segments.push((std::mem::take(&mut words), trailing));
+        }
+    }
+    if !words.is_empty() {
+        segments.push((words, ""));
+    }
+
+    let mut output = vec![];
+    let mut lines = 0;
+    for (words, trailing) in segments {
+        let block = Block {
+            prefix: &prefix,
+            suffix: "",
+            marker: "",
+            words,
+            newline_after: false,
+        };
+        let (text, diagnostics) = reformat::with_diagnostics(opts, |opts| {
+            Reformatter::with_blocks(opts, vec![block]).try_reformatted()
+        });
+        let mut text = text?;
+        reformat::forward(opts, diagnostics, lines);
+        lines += text.split('\n').count();
+        text.push_str(trailing);
+        output.push(text);
+    }
+    let output = output.join("\n");
+
+    Ok(match output.strip_prefix(prefix.as_str()) {
+        Some(rest) if para.nested => format!("{}{}", first_prefix, rest),
+        _ => output,
+    })
+}
+
+/// Rewrap every paragraph in a markdown document, including those nested in
+/// list items, blockquotes and footnote definitions. Each paragraph's range
+/// covers whole lines, without the final line ending; everything outside these
+/// ranges is left as it is.
+pub fn rewrapped(opts: &FormatOpts, input: &str) -> Result<Vec<Rewrapped>, Error> {
+    let mut paragraphs = vec![];
+    let mut pos = 0;
+    for para in find_paragraphs(input) {
+        let start = line_start(input, para.range.start);
+        if start < pos {
+            // shares a line with the previous paragraph; leave it alone
+            continue;
+        }
+        pos = line_end(input, para.range.end);
+        paragraphs.push((start..pos, para));
+    }
+    crate::map_in_order(paragraphs, |(range, para)| {
+        let (text, diagnostics) =
+            reformat::with_diagnostics(opts, |opts| reformat_paragraph(opts, input, &para));
+        Ok(Rewrapped {
+            range,
+            text: text?,
+            diagnostics,
+        })
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Reformat a Rust doc comment, with `///` or `//!` starting every line, as
+/// markdown: fenced examples, headings, lists and link reference definitions
+/// keep their shape. `None` if `input` isn't a doc comment.
+pub(crate) fn reformat_doc_comment(
+    opts: &FormatOpts,
+    input: &str,
+) -> Option<Result<String, Error>> {
+    let first = input.lines().next()?;
+    let indentation = &first[..first.len() - first.trim_start().len()];
+    let marker = ["///", "//!"]
+        .into_iter()
+        .find(|marker| first[indentation.len()..].starts_with(marker))?;
+    let bare = format!("{}{}", indentation, marker);
+    let prefix = format!("{} ", bare);
+    let body = input
+        .lines()
+        .map(|line| {
+            if line.trim_end() == bare {
+                Some("")
+            } else {
+                line.strip_prefix(prefix.as_str())
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let width: usize = prefix
+        .chars()
+        .map(|c| if c == '\t' { opts.tab_width } else { 1 })
+        .sum();
+    let opts = FormatOpts {
+        format_mode: FormatMode::Markdown,
+        max_length: opts.max_length.saturating_sub(width).max(1),
+        ..opts.clone()
+    };
+    Some(
+        reformat::reformat_unchecked(&opts, &body.join("\n")).map(|text| {
+            text.split('\n')
+                .map(|line| {
+                    if line.is_empty() {
+                        bare.clone()
+                    } else {
+                        format!("{}{}", prefix, line)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        }),
+    )
+}
@@ -1,20 +1,184 @@
 // use itertools::Itertools;
-use crate::analysis::{Block, Input, Token, Width};
+use crate::analysis::{Block, Input, Token, Width, list_marker};
+use crate::ansi::{self, Style};
+use crate::breaks::{self, Join, Piece};
+use crate::commit;
+use crate::error::{Diagnostic, Error};
+use crate::markdown;
+use crate::source::{self, Language};
 use pathfinding::prelude::dijkstra;
-use unicode_width::UnicodeWidthStr;
+use std::ops::{Range, RangeInclusive};
+use std::sync::mpsc::{self, Sender};
 
+#[derive(Clone, Copy)]
 pub enum FormatMode {
     PlainText,
     Markdown,
     Code,
+    /// A whole source file, of which only the comments are rewrapped
+    Source(&'static Language),
+    /// A git commit message, whose subject and trailers are left alone
+    GitCommit,
 }
 
+impl FormatMode {
+    /// Whether input in this mode has to be read as a whole, rather than a
+    /// paragraph at a time
+    pub(crate) fn whole_document(&self) -> bool {
+        matches!(
+            self,
+            FormatMode::Markdown | FormatMode::Source(_) | FormatMode::GitCommit
+        )
+    }
+}
+
+/// Where each line sits between its prefix and suffix
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Alignment {
+    #[default]
+    Left,
+    Right,
+    Center,
+}
+
+/// How the last line of a paragraph is charged by the optimizer
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LastLine {
+    /// The last line costs nothing, however short it is
+    #[default]
+    Free,
+    /// The last line is charged like every other line
+    Slack,
+}
+
+/// Algorithm used to choose where to break lines
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Solver {
+    /// A shortest path search over every possible line
+    #[default]
+    ShortestPath,
+    /// Dynamic programming over the lines that fit, which also tries every
+    /// width for `reduce_jaggedness` in a single pass. Its time grows with the
+    /// number of words, times the words that fit on a line, times the widths
+    /// tried; it isn't linear in the length of the paragraph, as the monotone
+    /// (SMAWK) speedup doesn't hold with the penalties in `CostModel`. Still
+    /// faster than `ShortestPath` on long paragraphs, especially with
+    /// `reduce_jaggedness`. Finds breaks that cost the same, though it may
+    /// pick a different one of several equally good ones.
+    DynamicProgramming,
+}
+
+/// What to do with a word that is wider than the target width
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LongWordPolicy {
+    /// Let it extend beyond the target width
+    #[default]
+    Allow,
+    /// Break it wherever it has to be, between grapheme clusters
+    Break,
+    /// Break it after a `/`, `?`, `&` or `-`, as suits URLs and paths, and
+    /// let any part still too wide extend beyond the target width
+    BreakAtUrlPoints,
+    /// Fail with `Error::LongWord`; `reformat` then returns the input as it was
+    Fail,
+}
+
+/// The characters that end each line of output
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Costs used when choosing where to break lines. The defaults give prose's
+/// standard output.
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    /// Exponent applied to a line's slack (the columns left unused before the
+    /// target width). Very large exponents can overflow on long paragraphs.
+    pub slack_exponent: u32,
+    /// Cost of a line that has to extend beyond the target width because a
+    /// word doesn't fit on a line by itself
+    pub overage_penalty: u64,
+    /// How the last line is charged. Setting `FormatOpts::last_line` is the
+    /// same as `LastLine::Slack`.
+    pub last_line: LastLine,
+    /// Multiplier for the squared distance between a narrower width tried by
+    /// `reduce_jaggedness` and the target width
+    pub fit_penalty: u64,
+    /// Extra cost when the last line of a paragraph holds a single word
+    pub widow_penalty: u64,
+    /// Extra cost for breaking a line right after the first word of a sentence
+    pub sentence_start_penalty: u64,
+    /// The fraction of the target width (0.0 to 1.0) that the last line should
+    /// fill. Each column of shortfall costs as much as an overage, so a shorter
+    /// last line is only used when nothing else will do.
+    pub last_line_min_fill: f64,
+    /// Extra cost for breaking a line in the middle of a word
+    pub hyphen_penalty: u64,
+    /// Extra cost for starting a line with a word that would be read as a
+    /// list marker, such as a lone `-`, or as markdown block syntax, such as
+    /// `#` or `>`, when the output is read again
+    pub marker_penalty: u64,
+}
+
+impl Default for CostModel {
+    fn default() -> Self {
+        CostModel {
+            slack_exponent: 2,
+            overage_penalty: 100_000,
+            last_line: LastLine::Free,
+            fit_penalty: 1,
+            widow_penalty: 0,
+            sentence_start_penalty: 0,
+            last_line_min_fill: 0.0,
+            hyphen_penalty: 150,
+            marker_penalty: 1_000_000,
+        }
+    }
+}
+
+/// How text is reformatted. Lines are broken at spaces, and between the
+/// characters of Chinese and Japanese text where UAX #14 allows. Thai, Lao,
+/// Khmer and Burmese text, whose words can only be found with a dictionary, is
+/// broken at spaces alone.
+#[derive(Clone)]
 pub struct FormatOpts {
     pub max_length: usize,
     pub tab_width: usize,
     pub last_line: bool,
     pub reduce_jaggedness: bool,
     pub format_mode: FormatMode,
+    /// Spread extra spaces between words so that every line but the last in
+    /// a paragraph fills the target width
+    pub justify: bool,
+    /// Allow long words to be broken with a hyphen where the (English)
+    /// hyphenation patterns permit. Needs the `hyphenation` feature; soft
+    /// hyphens in the input are honored either way.
+    pub hyphenate: bool,
+    /// Don't count terminal escape sequences (colors, OSC 8 hyperlinks) towards
+    /// the width of a line, and restart any active style on each new line
+    pub ansi: bool,
+    pub alignment: Alignment,
+    pub cost_model: CostModel,
+    pub solver: Solver,
+    pub long_words: LongWordPolicy,
+    /// Line ending to write. By default each paragraph keeps the one it was
+    /// read with.
+    pub line_ending: Option<LineEnding>,
+    /// Where to send diagnostics, such as words too wide for the target
+    /// width. Without a sender they are dropped.
+    pub diagnostics: Option<Sender<Diagnostic>>,
 }
 
 impl Default for FormatOpts {
@@ -25,6 +189,15 @@ impl Default for FormatOpts {
             reduce_jaggedness: false,
             tab_width: 4,
             format_mode: FormatMode::PlainText,
+            justify: false,
+            hyphenate: false,
+            ansi: false,
+            alignment: Alignment::Left,
+            cost_model: CostModel::default(),
+            solver: Solver::ShortestPath,
+            long_words: LongWordPolicy::Allow,
+            line_ending: None,
+            diagnostics: None,
         }
     }
 }
@@ -51,19 +224,167 @@ impl FormatOpts {
             last_line,
             reduce_jaggedness,
             format_mode,
+            justify: false,
+            hyphenate: false,
+            ansi: false,
+            alignment: Alignment::Left,
+            cost_model: CostModel::default(),
+            solver: Solver::ShortestPath,
+            long_words: LongWordPolicy::Allow,
+            line_ending: None,
+            diagnostics: None,
+        }
+    }
+}
+
+/// Call `f` with a copy of `opts` that collects its diagnostics, and return
+/// them along with its result
+pub(crate) fn with_diagnostics<T>(
+    opts: &FormatOpts,
+    f: impl FnOnce(&FormatOpts) -> T,
+) -> (T, Vec<Diagnostic>) {
+    let (sender, receiver) = mpsc::channel();
+    let collecting = FormatOpts {
+        diagnostics: Some(sender),
+        ..opts.clone()
+    };
+    let result = f(&collecting);
+    drop(collecting);
+    (result, receiver.iter().collect())
+}
+
+/// Pass diagnostics on to `opts.diagnostics`, moved `lines` lines down
+pub(crate) fn forward(opts: &FormatOpts, diagnostics: Vec<Diagnostic>, lines: usize) {
+    send(
+        opts.diagnostics.as_ref(),
+        diagnostics.into_iter().map(|d| d.offset(lines)),
+    );
+}
+
+/// Send diagnostics, if there is anywhere to send them
+fn send(sender: Option<&Sender<Diagnostic>>, diagnostics: impl IntoIterator<Item = Diagnostic>) {
+    if let Some(sender) = sender {
+        for diagnostic in diagnostics {
+            // nobody may be listening any more, which is fine
+            let _ = sender.send(diagnostic);
         }
     }
 }
 
+/// Check for options that can't be used
+fn validate(opts: &FormatOpts) -> Result<(), Error> {
+    if opts.max_length == 0 {
+        return Err(Error::InvalidOptions("max_length must be at least 1"));
+    }
+    if !(0.0..=1.0).contains(&opts.cost_model.last_line_min_fill) {
+        return Err(Error::InvalidOptions(
+            "last_line_min_fill must be between 0.0 and 1.0",
+        ));
+    }
+    Ok(())
+}
+
+/// A place where a line may be broken, before the piece with the same index
 #[derive(Debug)]
 struct Entry {
+    /// total width of the pieces before this point
     offset: usize,
+    /// number of pieces before this point that follow a space
+    spaces: usize,
+    /// number of pieces before this point that start a word, or a run of
+    /// characters that may be broken without a hyphen
+    units: usize,
+    /// whether the piece before this point is part of a sentence's first word
+    sentence_start: bool,
+    /// whether breaking here splits a word, which then needs a hyphen
+    mid_word: bool,
+    /// whether a line starting here would begin with a list marker or other
+    /// block syntax
+    marker: bool,
 }
 
-impl Entry {
-    fn new(offset: usize) -> Self {
-        Entry { offset }
+fn entries(pieces: &[Piece], width: impl Fn(&str) -> usize) -> Vec<Entry> {
+    let mut entries = Vec::with_capacity(pieces.len() + 1);
+    entries.push(Entry {
+        offset: 0,
+        spaces: 0,
+        units: 0,
+        sentence_start: false,
+        mid_word: false,
+        marker: false,
+    });
+    let mut offset = 0;
+    let mut spaces = 0;
+    let mut units = 0;
+    let mut sentence_start = false;
+    // the paragraph's first word starts a sentence too
+    let mut after_sentence = true;
+    for (k, piece) in pieces.iter().enumerate() {
+        match piece.join {
+            Join::Space => {
+                spaces += 1;
+                units += 1;
+                sentence_start = after_sentence;
+            }
+            Join::Direct => units += 1,
+            Join::Hyphen(_) => {}
+        }
+        offset += width(piece.text);
+        let mid_word = pieces
+            .get(k + 1)
+            .is_some_and(|p| matches!(p.join, Join::Hyphen(_)));
+        if !mid_word {
+            after_sentence = ends_sentence(piece.text);
+        }
+        entries.push(Entry {
+            offset,
+            spaces,
+            units,
+            sentence_start,
+            mid_word,
+            marker: starts_with_marker(&pieces[k + 1..]),
+        });
     }
+    entries
+}
+
+/// Whether a line starting with `pieces` would begin with a list marker, or
+/// with something markdown reads as the start of a block: a heading, quote,
+/// code fence, thematic break or setext underline, or an HTML block
+fn starts_with_marker(pieces: &[Piece]) -> bool {
+    let Some(first) = pieces.first() else {
+        return false;
+    };
+    if first.join != Join::Space {
+        return false;
+    }
+    let len = pieces[1..]
+        .iter()
+        .position(|p| p.join == Join::Space)
+        .map_or(pieces.len(), |n| n + 1);
+    let word: String = pieces[..len].iter().map(|p| p.text.trim_start()).collect();
+    let run_of = |c: char| word.chars().all(|w| w == c);
+    list_marker(&format!("{} x", word)) == Some(word.len() + 1)
+        || (word.len() <= 6 && run_of('#'))
+        || ['-', '=', '*', '_'].into_iter().any(run_of)
+        || word.starts_with('>')
+        || word.starts_with("```")
+        || word.starts_with("~~~")
+        || word.strip_prefix('<').is_some_and(|rest| {
+            rest.starts_with(|c: char| c.is_ascii_alphabetic() || c == '!' || c == '/')
+        })
+}
+
+/// Width of a line from piece `i` up to piece `j`
+fn line_width(entries: &[Entry], i: usize, j: usize) -> usize {
+    let spaces = entries[j].spaces - entries[i + 1].spaces;
+    let hyphen = entries[j].mid_word as usize;
+    entries[j].offset - entries[i].offset + spaces + hyphen
+}
+
+fn ends_sentence(word: &str) -> bool {
+    word.trim_end_matches(['"', '\'', ')', ']', '”', '’'])
+        .ends_with(['.', '!', '?', '。', '！', '？'])
 }
 
 fn spaces(n: usize) -> String {
@@ -76,36 +397,111 @@ pub struct Reformatter<'a> {
     target: usize,
     last_line: bool,
     fit: bool,
+    justify: bool,
+    hyphenate: bool,
+    ansi: bool,
+    alignment: Alignment,
+    cost_model: CostModel,
+    solver: Solver,
+    long_words: LongWordPolicy,
+    diagnostics: Option<Sender<Diagnostic>>,
+}
+
+/// The lines of one reformatted block
+struct Section {
+    lines: Vec<String>,
+    /// Width of the text, not counting the prefix and suffix
+    width: usize,
+    /// Lines held back while streaming
+    carry: Option<Carry>,
+    /// Words that extend beyond the target width, by index into `lines`, each
+    /// with its line given as 1
+    overflows: Vec<(usize, Diagnostic)>,
 }
 
 impl<'a> Reformatter<'a> {
     pub fn new(opts: &FormatOpts, input: &'a str) -> Reformatter<'a> {
-        let input = Input::with_input(input);
-        let blocks = match opts.format_mode {
-            FormatMode::Code => input.analyze_code_comments(),
-            _ => input.analyze_quotes(),
-        }
-        .unwrap_or_else(|| input.analyze_surround().unwrap());
-
         // eprintln!("Prefix: {}, Suffix: {}, Max: {}, Target: {}", prefix, suffix, opts.max_length, target);
+        Self::with_blocks(opts, analyze(opts, input))
+    }
+
+    /// Like `new`, but checks the options first
+    pub fn try_new(opts: &FormatOpts, input: &'a str) -> Result<Reformatter<'a>, Error> {
+        validate(opts)?;
+        Ok(Self::new(opts, input))
+    }
+
+    pub(crate) fn with_blocks(opts: &FormatOpts, blocks: Vec<Block<'a>>) -> Reformatter<'a> {
         Reformatter {
             blocks,
             target: opts.max_length,
-            last_line: opts.last_line,
+            last_line: opts.last_line || opts.cost_model.last_line == LastLine::Slack,
             fit: opts.reduce_jaggedness,
+            justify: opts.justify,
+            hyphenate: opts.hyphenate,
+            ansi: opts.ansi,
+            alignment: opts.alignment,
+            cost_model: opts.cost_model.clone(),
+            solver: opts.solver,
+            long_words: opts.long_words,
+            diagnostics: opts.diagnostics.clone(),
+        }
+    }
+
+    /// Display width of `s`
+    fn width(&self, s: &str) -> usize {
+        if self.ansi { ansi::width(s) } else { s.width() }
+    }
+
+    /// Cost of a line from piece `i` up to piece `j` that is `linew` wide and
+    /// fits within `target`
+    fn line_cost(&self, entries: &[Entry], i: usize, j: usize, linew: usize, target: usize) -> u64 {
+        let CostModel {
+            slack_exponent: exponent,
+            overage_penalty,
+            widow_penalty,
+            sentence_start_penalty,
+            last_line_min_fill,
+            hyphen_penalty,
+            marker_penalty,
+            ..
+        } = self.cost_model;
+        let is_last = j == entries.len() - 1;
+        let mut cost = if is_last && !self.last_line {
+            //handle last line
+            0
+        } else {
+            let diff = (target - linew) as u64;
+            diff.saturating_pow(exponent)
+        };
+        if is_last {
+            if i > 0 && entries[j].units == entries[i + 1].units {
+                cost = cost.saturating_add(widow_penalty);
+            }
+            let min_last_line = (last_line_min_fill * target as f64).ceil() as usize;
+            if linew < min_last_line {
+                let shortfall = (min_last_line - linew) as u64;
+                cost = cost.saturating_add(shortfall.saturating_mul(overage_penalty));
+            }
+        } else if entries[j].mid_word {
+            cost = cost.saturating_add(hyphen_penalty);
+        } else if entries[j].sentence_start {
+            cost = cost.saturating_add(sentence_start_penalty);
+        }
+        if !is_last && entries[j].marker {
+            cost = cost.saturating_add(marker_penalty);
         }
+        cost
     }
 
     fn successors<'b>(
-        &self,
+        &'b self,
         entries: &'b [Entry],
         i: usize,
         target: usize,
         allow_overage: bool,
     ) -> impl Iterator<Item = (usize, u64)> + 'b {
-        let word1_offset = entries[i].offset;
         let count = entries.len();
-        let last_line = self.last_line;
         let mut j = i + 1;
         let mut done = false;
         let mut emitted = false;
@@ -115,115 +511,318 @@ impl<'a> Reformatter<'a> {
                 return None;
             }
             let cur_j = j;
-            let linew = entries[cur_j].offset - word1_offset + cur_j - i - 1;
+            let linew = line_width(entries, i, cur_j);
             //width of all words + width of all spaces = total line width
             if linew > target {
                 done = true;
                 if !emitted && allow_overage {
                     // ensure there's always at least a bail-out option
                     // for the next word, but very expensive
-                    return Some((cur_j, 100_000u64));
+                    return Some((cur_j, self.cost_model.overage_penalty));
                 }
                 return None;
             }
             emitted = true;
-            let cost = if cur_j > count - 2 && !last_line {
-                //handle last line
-                0
-            } else {
-                let diff = (target - linew) as u64;
-                diff * diff
-            };
             j += 1;
-            Some((cur_j, cost))
+            Some((cur_j, self.line_cost(entries, i, cur_j, linew, target)))
         })
     }
 
-    fn solve(&self, words: &[Token<'a>], target: usize) -> (Vec<usize>, u64) {
-        let count = words.len();
-        let dummy = Entry::new(0);
-
-        let mut entries = vec![dummy];
-        let mut offset = 0;
-        for w in words {
-            let wid = w.width();
-            offset += wid;
-            entries.push(Entry::new(offset));
+    /// Find the best breaks for each target, from the widest to the narrowest
+    fn solve(
+        &self,
+        entries: &[Entry],
+        targets: RangeInclusive<usize>,
+    ) -> Result<Vec<(Vec<usize>, u64)>, Error> {
+        match self.solver {
+            Solver::ShortestPath => targets
+                .rev()
+                .map(|target| self.solve_shortest_path(entries, target))
+                .collect(),
+            Solver::DynamicProgramming => self.solve_dp(entries, targets),
         }
+    }
 
-        let result = dijkstra(
+    fn solve_shortest_path(
+        &self,
+        entries: &[Entry],
+        target: usize,
+    ) -> Result<(Vec<usize>, u64), Error> {
+        let count = entries.len() - 1;
+
+        dijkstra(
             &0,
-            |i| self.successors(&entries, *i, target, false),
+            |i| self.successors(entries, *i, target, false),
             |i| *i == count,
-        );
-
-        if let Some(tuple) = result {
-            tuple
-        } else {
-            eprintln!("Warning: allowing some words to extend beyond target width");
+        )
+        .or_else(|| {
             // try again, allowing overage
             dijkstra(
                 &0,
-                |i| self.successors(&entries, *i, target, true),
+                |i| self.successors(entries, *i, target, true),
                 |i| *i == count,
             )
-            .expect("Unable to find optimum solution")
+        })
+        .ok_or(Error::NoSolution)
+    }
+
+    /// Dynamic programming over the lines that fit, for every target in one
+    /// pass: the width of each possible line is worked out once, and charged
+    /// against each target it fits in. That's O(n·w·t) for n pieces, w pieces
+    /// per line and t targets.
+    fn solve_dp(
+        &self,
+        entries: &[Entry],
+        targets: RangeInclusive<usize>,
+    ) -> Result<Vec<(Vec<usize>, u64)>, Error> {
+        let count = entries.len() - 1;
+        let (min_target, max_target) = (*targets.start(), *targets.end());
+        let stride = max_target - min_target + 1;
+        // for each break and target: the lowest cost of reaching it, and the
+        // break before it on that path
+        let mut best = vec![(u64::MAX, 0); (count + 1) * stride];
+        best[..stride].fill((0, 0));
+        for j in 1..=count {
+            for i in (0..j).rev() {
+                let linew = line_width(entries, i, j);
+                if linew > max_target && i + 1 < j {
+                    break;
+                }
+                for t in 0..stride {
+                    let target = min_target + t;
+                    let before = best[i * stride + t].0;
+                    let cost = if before == u64::MAX {
+                        continue;
+                    } else if linew <= target {
+                        self.line_cost(entries, i, j, linew, target)
+                    } else if i + 1 == j {
+                        // a piece wider than the target has to stick out
+                        self.cost_model.overage_penalty
+                    } else {
+                        continue;
+                    };
+                    let total = before.saturating_add(cost);
+                    let slot = &mut best[j * stride + t];
+                    if total < slot.0 {
+                        *slot = (total, i);
+                    }
+                }
+            }
         }
+
+        (0..stride)
+            .rev()
+            .map(|t| {
+                let cost = best[count * stride + t].0;
+                if cost == u64::MAX {
+                    return Err(Error::NoSolution);
+                }
+                let mut path = vec![count];
+                let mut j = count;
+                while j > 0 {
+                    j = best[j * stride + t].1;
+                    path.push(j);
+                }
+                path.reverse();
+                Ok((path, cost))
+            })
+            .collect()
     }
 
-    fn reformat_section(&self, block: &Block) -> (Vec<String>, usize) {
-        let words = &block.words;
-        let rawtarget =
-            self.target as i64 - block.prefix.width() as i64 - block.suffix.width() as i64;
+    /// Reformat one block. The last `keep` lines are left out and returned as
+    /// a `Carry` instead.
+    fn reformat_section(&self, block: &Block, keep: usize) -> Result<Section, Error> {
+        let marker_width = block.marker.width();
+        let rawtarget = self.target as i64
+            - self.width(block.prefix) as i64
+            - self.width(block.suffix) as i64
+            - marker_width as i64;
         let target = std::cmp::max(rawtarget, 1) as usize;
 
+        let mut pieces = breaks::pieces(&block.words, self.hyphenate);
+        match self.long_words {
+            LongWordPolicy::Allow => {}
+            LongWordPolicy::Fail => {
+                if let Some(piece) = pieces.iter().find(|p| self.width(p.text) > target) {
+                    return Err(Error::LongWord {
+                        word: piece.text.trim_start().to_string(),
+                        width: self.width(piece.text),
+                        target,
+                    });
+                }
+            }
+            policy => pieces = breaks::split_long(pieces, policy, target, self.ansi),
+        }
+
         let min_target = if self.fit { target / 2 } else { target };
         let max_target = target;
 
         let mut path = vec![];
-        let mut best_cost = 100_000_000;
+        let mut best_cost = None;
         let mut best_target = max_target;
 
-        for target in (min_target..=max_target).rev() {
-            let (p, cost) = self.solve(words, target);
+        let entries = entries(&pieces, |s| self.width(s));
+        let solutions = self.solve(&entries, min_target..=max_target)?;
+        for (target, (p, cost)) in (min_target..=max_target).rev().zip(solutions) {
             let target_distance = max_target as u64 - target as u64;
             // higher cost the further from original target
-            let cost = cost + target_distance * target_distance;
-            if cost < best_cost {
-                best_cost = cost;
+            let cost = cost.saturating_add(
+                self.cost_model
+                    .fit_penalty
+                    .saturating_mul(target_distance * target_distance),
+            );
+            if best_cost.is_none_or(|best| cost < best) {
+                best_cost = Some(cost);
                 path = p;
                 best_target = target;
             }
         }
 
         let mut lines: Vec<String> = vec![];
+        let mut overflows = vec![];
+        let last = path.len().saturating_sub(2);
+        let mut style = Style::default();
 
-        for s in path.windows(2) {
+        for (n, s) in path.windows(2).enumerate() {
             if let [start, end] = *s {
                 let mut l = block.prefix.to_string();
-                for (idx, w) in words[start..end].iter().enumerate() {
-                    if idx > 0 {
-                        l.push(' ');
+                if n == 0 {
+                    l.push_str(block.marker);
+                } else {
+                    l.push_str(&spaces(marker_width));
+                }
+                if self.ansi {
+                    l.push_str(&style.restore());
+                }
+                let content_start = l.len();
+                let line_pieces = &pieces[start..end];
+                let hyphen = pieces
+                    .get(end)
+                    .is_some_and(|p| matches!(p.join, Join::Hyphen(_)));
+                let gaps = line_pieces
+                    .iter()
+                    .skip(1)
+                    .filter(|p| p.join == Join::Space)
+                    .count();
+                let used = line_pieces
+                    .iter()
+                    .map(|p| self.width(p.text))
+                    .sum::<usize>()
+                    + gaps
+                    + hyphen as usize;
+                if used > target {
+                    // only a line holding a single piece can be too wide
+                    let word = line_pieces.iter().map(|p| p.text).collect::<String>();
+                    let diagnostic = Diagnostic::Overflow {
+                        line: 1,
+                        word: word.trim_start().to_string(),
+                        width: used,
+                        target,
+                    };
+                    overflows.push((n, diagnostic));
+                }
+                let mut extra = if self.justify && n < last && gaps > 0 {
+                    best_target.saturating_sub(used)
+                } else {
+                    0
+                };
+                let mut gap = 0;
+                for (idx, p) in line_pieces.iter().enumerate() {
+                    match p.join {
+                        _ if idx == 0 => {}
+                        Join::Hyphen(glue) => l.push_str(glue),
+                        Join::Direct => {}
+                        Join::Space => {
+                            // spread evenly, with any remainder going to the left
+                            let remaining_gaps = gaps - gap;
+                            let pad = extra.div_ceil(remaining_gaps);
+                            extra -= pad;
+                            gap += 1;
+                            l.push_str(&spaces(pad + 1));
+                        }
+                    }
+                    l.push_str(p.text);
+                }
+                if hyphen {
+                    l.push('-');
+                }
+                if self.ansi {
+                    style.update(&l[content_start..]);
+                    if n < last {
+                        // so that colors don't run into the suffix or the next prefix
+                        l.push_str(style.end());
                     }
-                    l.push_str(w.as_ref());
                 }
                 lines.push(l);
             }
         }
+        let mut carry = None;
+        if keep > 0 {
+            let kept = lines.len().saturating_sub(keep);
+            carry = Some(Carry {
+                prefix: block.prefix.to_string(),
+                suffix: block.suffix.to_string(),
+                // the marker only stays if the first line is held back too
+                marker: if kept == 0 {
+                    block.marker.to_string()
+                } else {
+                    spaces(marker_width)
+                },
+                words: breaks::words(&pieces[path[kept]..]),
+            });
+            lines.truncate(kept);
+            overflows.retain(|&(n, _)| n < kept);
+        }
         if block.newline_after {
             let extra = block.prefix.trim_end().to_string();
             lines.push(extra);
         }
-        (lines, best_target)
+        Ok(Section {
+            lines,
+            width: best_target + marker_width,
+            carry,
+            overflows,
+        })
     }
 
+    /// The reformatted text. If no line breaks can be found, which shouldn't
+    /// happen, each block is left on a line of its own.
     pub fn reformatted(&self) -> String {
+        self.try_reformatted().unwrap_or_else(|_| self.unwrapped())
+    }
+
+    pub fn try_reformatted(&self) -> Result<String, Error> {
+        Ok(self.reformatted_holding_back(0)?.0)
+    }
+
+    /// Each block on a single line
+    fn unwrapped(&self) -> String {
+        let mut lines = vec![];
+        for block in &self.blocks {
+            let words = block.words.join(" ");
+            lines.push(format!(
+                "{}{}{}{}",
+                block.prefix, block.marker, words, block.suffix
+            ));
+            if block.newline_after {
+                lines.push(block.prefix.trim_end().to_string());
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Reformat everything but the last `keep` lines, which are held back
+    fn reformatted_holding_back(&self, keep: usize) -> Result<(String, Option<Carry>), Error> {
         // get "unadorned" body
-        let sections: Vec<_> = self
-            .blocks
-            .iter()
-            .map(|s| (s, self.reformat_section(s)))
-            .collect();
+        let count = self.blocks.len();
+        let mut carry = None;
+        let mut sections = vec![];
+        for (n, block) in self.blocks.iter().enumerate() {
+            let keep = if n + 1 == count { keep } else { 0 };
+            let section = self.reformat_section(block, keep)?;
+            carry = section.carry;
+            sections.push((block, (section.lines, section.width, section.overflows)));
+        }
         let max_padding = sections
             .iter()
             .map(|s| (s.1).1)
@@ -232,13 +831,32 @@ impl<'a> Reformatter<'a> {
 
         let mut output = vec![];
 
-        for (block, (body, _)) in sections {
-            let suffix_length = block.suffix.width();
-            let prefix_length = block.prefix.width();
+        for (block, (body, width, overflows)) in sections {
+            let lines = output.len();
+            send(
+                self.diagnostics.as_ref(),
+                overflows.into_iter().map(|(n, d)| d.offset(lines + n)),
+            );
+            let suffix_length = self.width(block.suffix);
+            let prefix_length = self.width(block.prefix);
+            // with a suffix, every line is padded out to the widest section
+            let frame = if suffix_length > 0 {
+                max_padding as usize
+            } else {
+                width
+            };
 
-            for l in body {
+            for mut l in body {
+                if self.alignment != Alignment::Left && l.len() > block.prefix.len() {
+                    let slack = (frame + prefix_length).saturating_sub(self.width(&l));
+                    let indent = match self.alignment {
+                        Alignment::Right => slack,
+                        _ => slack / 2,
+                    };
+                    l.insert_str(block.prefix.len(), &spaces(indent));
+                }
                 if suffix_length > 0 {
-                    let l_width = l.width() as i64;
+                    let l_width = self.width(&l) as i64;
                     let mut line = l;
                     let pad_amount = max_padding - l_width + prefix_length as i64;
                     let pad = spaces(std::cmp::max(pad_amount, 0i64) as usize);
@@ -250,27 +868,30 @@ impl<'a> Reformatter<'a> {
                 }
             }
         }
-        output.join("\n")
+        Ok((output.join("\n"), carry))
     }
 }
 
+/// Reformat `input`. If that fails, which shouldn't happen, the input is
+/// returned as it was.
 pub fn reformat(opts: &FormatOpts, input: &str) -> String {
-    use FormatMode::*;
-    use pulldown_cmark::{Event, Options, Parser, Tag};
-
-    let do_reformat = match opts.format_mode {
-        Markdown => {
-            let mut parser = Parser::new_ext(input, Options::empty());
-            let pair = (parser.next(), parser.next());
-            matches!(
-                pair,
-                (Some(Event::Start(Tag::Paragraph)), Some(Event::Text(_)))
-            )
-        }
-        _ => true,
-    };
+    reformat_unchecked(opts, input).unwrap_or_else(|_| input.to_string())
+}
 
-    if do_reformat {
+/// Like `reformat`, but checks the options first and reports any failure
+pub fn try_reformat(opts: &FormatOpts, input: &str) -> Result<String, Error> {
+    validate(opts)?;
+    reformat_unchecked(opts, input)
+}
+
+pub(crate) fn reformat_unchecked(opts: &FormatOpts, input: &str) -> Result<String, Error> {
+    if opts.format_mode.whole_document() {
+        Ok(splice(opts, input, rewrapped(opts, input)?))
+    } else if let FormatMode::Code = opts.format_mode
+        && let Some(result) = markdown::reformat_doc_comment(opts, input)
+    {
+        result
+    } else {
         let cleaned_input = if input.find('\t').is_some() {
             let expanded = spaces(opts.tab_width);
             Token::Owned(input.replace('\t', &expanded))
@@ -279,8 +900,117 @@ pub fn reformat(opts: &FormatOpts, input: &str) -> String {
         };
 
         let rfmt = Reformatter::new(opts, &cleaned_input);
-        rfmt.reformatted()
+        rfmt.try_reformatted()
+    }
+}
+
+/// A rewrapped region of a document read as a whole: the byte range it
+/// replaces (whole lines, without the final line ending), its new text, and
+/// the diagnostics for that
+pub(crate) struct Rewrapped {
+    pub range: Range<usize>,
+    pub text: String,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// The regions of a document that get rewrapped, in modes that read it as a
+/// whole. Everything outside them is left as it is.
+pub(crate) fn rewrapped(opts: &FormatOpts, input: &str) -> Result<Vec<Rewrapped>, Error> {
+    match opts.format_mode {
+        FormatMode::Source(lang) => source::rewrapped(opts, lang, input),
+        FormatMode::GitCommit => commit::rewrapped(opts, input),
+        _ => markdown::rewrapped(opts, input),
+    }
+}
+
+/// Put rewrapped regions in place of the text they replace, forwarding their
+/// diagnostics. Like the other modes, the final line ending is dropped.
+fn splice(opts: &FormatOpts, input: &str, regions: Vec<Rewrapped>) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut pos = 0;
+    let mut lines = 0;
+    for region in regions {
+        let between = &input[pos..region.range.start];
+        lines += between.matches('\n').count();
+        forward(opts, region.diagnostics, lines);
+        lines += region.text.matches('\n').count();
+        output.push_str(between);
+        output.push_str(&region.text);
+        pos = region.range.end;
+    }
+    output.push_str(&input[pos..]);
+    if output.ends_with('\n') {
+        output.pop();
+        if output.ends_with('\r') {
+            output.pop();
+        }
+    }
+    output
+}
+
+fn analyze<'a>(opts: &FormatOpts, input: &'a str) -> Vec<Block<'a>> {
+    let input = Input::with_input(input);
+    match opts.format_mode {
+        FormatMode::Code => input
+            .analyze_block_comment()
+            .or_else(|| input.analyze_code_comments()),
+        _ => input.analyze_quotes(),
+    }
+    .unwrap_or_else(|| input.analyze_surround().unwrap())
+}
+
+/// The end of a long paragraph, held back while streaming so that it can be
+/// reformatted again along with the lines that follow it
+pub(crate) struct Carry {
+    prefix: String,
+    suffix: String,
+    marker: String,
+    words: Vec<String>,
+}
+
+/// Reformat one chunk of a paragraph that may continue in later chunks. All
+/// but the last `keep` lines are final; the rest are carried into the next
+/// call and joined to its first block, as long as that has the same prefix and
+/// suffix.
+pub(crate) fn reformat_chunk(
+    opts: &FormatOpts,
+    input: &str,
+    carry: Option<Carry>,
+    keep: usize,
+) -> Result<(String, Option<Carry>), Error> {
+    let cleaned_input = input.replace('\t', &spaces(opts.tab_width));
+    let mut blocks = if cleaned_input.is_empty() {
+        vec![]
     } else {
-        input.to_string()
+        analyze(opts, &cleaned_input)
+    };
+    if let Some(carry) = &carry {
+        let words = carry.words.iter().map(|w| Token::Borrowed(w.as_str()));
+        match blocks.first_mut() {
+            Some(first)
+                if first.marker.is_empty()
+                    && first.prefix.trim_end() == carry.prefix.trim_end()
+                    && first.suffix.trim_start() == carry.suffix.trim_start() =>
+            {
+                if let Some(Token::Borrowed(word)) = first.words.first_mut() {
+                    *word = word.trim_start();
+                }
+                first.prefix = &carry.prefix;
+                first.suffix = &carry.suffix;
+                first.marker = &carry.marker;
+                first.words.splice(0..0, words);
+            }
+            _ => blocks.insert(
+                0,
+                Block {
+                    prefix: &carry.prefix,
+                    suffix: &carry.suffix,
+                    marker: &carry.marker,
+                    words: words.collect(),
+                    newline_after: false,
+                },
+            ),
+        }
     }
+    Reformatter::with_blocks(opts, blocks).reformatted_holding_back(keep)
 }
@@ -0,0 +1,475 @@
+use crate::analysis::{is_comment_closer, is_comment_opener};
+use crate::docstring;
+use crate::error::Error;
+use crate::reformat::{self, FormatMode, FormatOpts, Rewrapped};
+use std::ops::Range;
+use std::path::Path;
+
+/// How comments and string literals are written in a programming language
+#[derive(Debug)]
+pub struct Language {
+    name: &'static str,
+    extensions: &'static [&'static str],
+    /// Line comment markers, longest first
+    line_comments: &'static [&'static str],
+    /// Opening and closing block comment delimiters
+    block_comment: Option<(&'static str, &'static str)>,
+    /// String delimiters, longest first, and whether a backslash escapes the
+    /// character after it
+    strings: &'static [(&'static str, bool)],
+    /// Raw strings like `r#"..."#`
+    raw_strings: bool,
+    /// A `'` starts a character literal only if one follows, since it may
+    /// also start a lifetime
+    lifetimes: bool,
+    /// Triple-quoted strings that start a module or follow a `:` are
+    /// docstrings, and get rewrapped too
+    docstrings: bool,
+}
+
+const C_STRINGS: &[(&str, bool)] = &[("\"", true), ("'", true)];
+const JS_STRINGS: &[(&str, bool)] = &[("\"", true), ("'", true), ("`", true)];
+const SCRIPT_STRINGS: &[(&str, bool)] = &[("\"", true), ("'", false)];
+const C_BLOCK: Option<(&str, &str)> = Some(("/*", "*/"));
+
+const fn c_like(
+    name: &'static str,
+    extensions: &'static [&'static str],
+    line_comments: &'static [&'static str],
+    strings: &'static [(&'static str, bool)],
+) -> Language {
+    Language {
+        name,
+        extensions,
+        line_comments,
+        block_comment: C_BLOCK,
+        strings,
+        raw_strings: false,
+        lifetimes: false,
+        docstrings: false,
+    }
+}
+
+const fn hash_comments(
+    name: &'static str,
+    extensions: &'static [&'static str],
+    strings: &'static [(&'static str, bool)],
+) -> Language {
+    Language {
+        name,
+        extensions,
+        line_comments: &["#"],
+        block_comment: None,
+        strings,
+        raw_strings: false,
+        lifetimes: false,
+        docstrings: false,
+    }
+}
+
+/// The languages that source files can be written in
+pub static LANGUAGES: &[Language] = &[
+    Language {
+        name: "rust",
+        extensions: &["rs"],
+        line_comments: &["///", "//!", "//"],
+        block_comment: C_BLOCK,
+        strings: &[("\"", true)],
+        raw_strings: true,
+        lifetimes: true,
+        docstrings: false,
+    },
+    c_like("c", &["c", "h"], &["//"], C_STRINGS),
+    c_like(
+        "cpp",
+        &["cc", "cpp", "cxx", "hh", "hpp", "hxx"],
+        &["///", "//"],
+        C_STRINGS,
+    ),
+    c_like("csharp", &["cs"], &["///", "//"], C_STRINGS),
+    c_like(
+        "go",
+        &["go"],
+        &["//"],
+        &[("\"", true), ("'", true), ("`", false)],
+    ),
+    c_like("java", &["java"], &["//"], C_STRINGS),
+    c_like(
+        "javascript",
+        &["js", "jsx", "mjs", "cjs"],
+        &["//"],
+        JS_STRINGS,
+    ),
+    c_like(
+        "typescript",
+        &["ts", "tsx", "mts", "cts"],
+        &["//"],
+        JS_STRINGS,
+    ),
+    c_like("kotlin", &["kt", "kts"], &["//"], C_STRINGS),
+    c_like("scala", &["scala", "sc"], &["//"], C_STRINGS),
+    c_like("swift", &["swift"], &["///", "//"], C_STRINGS),
+    c_like("css", &["css", "scss", "less"], &["//"], C_STRINGS),
+    Language {
+        docstrings: true,
+        ..hash_comments(
+            "python",
+            &["py", "pyi"],
+            &[("\"\"\"", true), ("'''", true), ("\"", true), ("'", true)],
+        )
+    },
+    hash_comments("ruby", &["rb"], SCRIPT_STRINGS),
+    hash_comments("shell", &["sh", "bash", "zsh"], SCRIPT_STRINGS),
+    hash_comments("perl", &["pl", "pm"], SCRIPT_STRINGS),
+    hash_comments("r", &["r"], C_STRINGS),
+    hash_comments("toml", &["toml"], SCRIPT_STRINGS),
+    hash_comments("yaml", &["yaml", "yml"], SCRIPT_STRINGS),
+    Language {
+        name: "lua",
+        extensions: &["lua"],
+        line_comments: &["--"],
+        block_comment: Some(("--[[", "]]")),
+        strings: C_STRINGS,
+        raw_strings: false,
+        lifetimes: false,
+        docstrings: false,
+    },
+    Language {
+        name: "sql",
+        extensions: &["sql"],
+        line_comments: &["--"],
+        block_comment: C_BLOCK,
+        strings: &[("'", false), ("\"", false)],
+        raw_strings: false,
+        lifetimes: false,
+        docstrings: false,
+    },
+    Language {
+        name: "haskell",
+        extensions: &["hs"],
+        line_comments: &["--"],
+        block_comment: Some(("{-", "-}")),
+        strings: &[("\"", true)],
+        raw_strings: false,
+        lifetimes: true,
+        docstrings: false,
+    },
+    Language {
+        name: "lisp",
+        extensions: &["lisp", "el", "clj", "cljs", "scm"],
+        line_comments: &[";;;", ";;", ";"],
+        block_comment: None,
+        strings: &[("\"", true)],
+        raw_strings: false,
+        lifetimes: false,
+        docstrings: false,
+    },
+];
+
+impl Language {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The language called `name`, or with `name` as its file extension
+    pub fn named(name: &str) -> Option<&'static Language> {
+        let name = name.to_ascii_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|lang| lang.name == name || lang.extensions.contains(&name.as_str()))
+    }
+
+    /// The language of a source file, from its extension
+    pub fn for_path(path: &Path) -> Option<&'static Language> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        LANGUAGES
+            .iter()
+            .find(|lang| lang.extensions.contains(&extension.as_str()))
+    }
+}
+
+/// Byte length of the string literal at the start of `s`, which opens with
+/// `delimiter`. An unterminated one runs to the end.
+fn string_len(s: &str, delimiter: &str, escapes: bool) -> usize {
+    let mut pos = delimiter.len();
+    while pos < s.len() {
+        let rest = &s[pos..];
+        if rest.starts_with(delimiter) {
+            return pos + delimiter.len();
+        }
+        let c = rest.chars().next().unwrap_or_default();
+        pos += c.len_utf8();
+        if escapes && c == '\\' {
+            pos += rest[1..].chars().next().map_or(0, char::len_utf8);
+        }
+    }
+    s.len()
+}
+
+/// Byte length of the raw string literal (`r"..."`, `r#"..."#`, or a byte or
+/// C string like `br"..."`) at the start of `s`, if there is one
+fn raw_string_len(s: &str) -> Option<usize> {
+    let prefix = usize::from(s.starts_with(['b', 'c']));
+    let hashes = s[prefix..]
+        .strip_prefix('r')?
+        .bytes()
+        .take_while(|&b| b == b'#')
+        .count();
+    let body = s[prefix + 1 + hashes..].strip_prefix('"')?;
+    let closing = format!("\"{}", "#".repeat(hashes));
+    let len = body
+        .find(&closing)
+        .map_or(body.len(), |i| i + closing.len());
+    Some(prefix + 2 + hashes + len)
+}
+
+/// Byte length of the character literal at the start of `s`, if it is one
+/// and not a lifetime
+fn char_literal_len(s: &str) -> Option<usize> {
+    let rest = s.strip_prefix('\'')?;
+    let len = if rest.starts_with('\\') {
+        2 + rest.get(2..)?.find('\'')?
+    } else {
+        rest.chars().next()?.len_utf8()
+    };
+    rest[len..].starts_with('\'').then_some(len + 2)
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    /// A line comment, starting with this marker
+    Line(&'static str),
+    Block,
+    Docstring,
+}
+
+/// A comment in source code, and its byte range
+type Comment = (Range<usize>, Kind);
+
+/// Whether the triple-quoted string at `pos` is a docstring: the first thing
+/// in the file, or the first thing on its line after a line ending with `:`,
+/// such as a `def`. `comments` are those before it.
+fn is_docstring(source: &str, pos: usize, comments: &[Comment]) -> bool {
+    let line_start = source[..pos].rfind('\n').map_or(0, |i| i + 1);
+    if !matches!(source[line_start..pos].trim(), "" | "r" | "R" | "u" | "U") {
+        return false;
+    }
+    let mut end = source[..line_start].trim_end().len();
+    for (range, _) in comments.iter().rev() {
+        if range.end != end {
+            break;
+        }
+        end = source[..range.start].trim_end().len();
+    }
+    end == 0 || source[..end].ends_with(':')
+}
+
+/// Every comment in `source`, skipping over string literals other than
+/// docstrings
+fn comments(lang: &Language, source: &str) -> Vec<Comment> {
+    let mut comments = vec![];
+    let mut pos = 0;
+    while pos < source.len() {
+        let rest = &source[pos..];
+        if let Some((open, close)) = lang.block_comment
+            && rest.starts_with(open)
+        {
+            let end = rest[open.len()..]
+                .find(close)
+                .map_or(source.len(), |i| pos + open.len() + i + close.len());
+            comments.push((pos..end, Kind::Block));
+            pos = end;
+        } else if let Some(marker) = lang.line_comments.iter().find(|m| rest.starts_with(*m)) {
+            let end = rest.find('\n').map_or(source.len(), |i| pos + i);
+            comments.push((pos..end, Kind::Line(marker)));
+            pos = end;
+        } else if let Some(len) = lang.raw_strings.then(|| raw_string_len(rest)).flatten() {
+            pos += len;
+        } else if lang.lifetimes && rest.starts_with('\'') {
+            pos += char_literal_len(rest).unwrap_or(1);
+        } else if let Some((delimiter, escapes)) =
+            lang.strings.iter().find(|(d, _)| rest.starts_with(d))
+        {
+            let len = string_len(rest, delimiter, *escapes);
+            if lang.docstrings && delimiter.len() == 3 && is_docstring(source, pos, &comments) {
+                comments.push((pos..pos + len, Kind::Docstring));
+            }
+            pos += len;
+        } else {
+            // step over identifiers whole, so that e.g. the `r` ending one
+            // isn't taken for the start of a raw string (raw strings with a
+            // `b` or `c` prefix were checked for above)
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            pos += len.max(rest.chars().next().map_or(1, char::len_utf8));
+        }
+    }
+    comments
+}
+
+/// Lines of a source file to rewrap, as a range of line indexes
+enum Region {
+    /// Line comments alone on their lines, with the same marker and
+    /// indentation, or a block comment whose delimiters are on lines of their
+    /// own
+    Comment(Range<usize>),
+    /// The lines between the first and last of a docstring, whose closing
+    /// quotes are on a line of their own
+    Docstring(Range<usize>),
+}
+
+/// The regions of `source` to rewrap, in order
+fn comment_regions(lang: &Language, source: &str, starts: &[usize]) -> Vec<Region> {
+    let line_of = |pos: usize| starts.partition_point(|&start| start <= pos) - 1;
+    let line_text = |n: usize| {
+        let end = starts.get(n + 1).copied().unwrap_or(source.len());
+        source[starts[n]..end].trim_end_matches(['\n', '\r'])
+    };
+    let mut regions = vec![];
+    // the marker and indentation of the line comment on the line before
+    let mut previous: Option<(usize, &str, &str)> = None;
+    for (range, kind) in comments(lang, source) {
+        let first = line_of(range.start);
+        let last = line_of(range.end.saturating_sub(1));
+        let after = starts.get(last + 1).map_or(source.len(), |&next| next);
+        let indentation = &source[starts[first]..range.start];
+        if let Kind::Docstring = kind {
+            // the opening line is left as it is, so that it may hold a
+            // string prefix
+            let closing = &source[range.start..range.start + 3];
+            if last > first + 1 && line_text(last).trim() == closing {
+                regions.push(Region::Docstring(first + 1..last));
+            }
+            previous = None;
+            continue;
+        }
+        if !indentation.trim().is_empty() {
+            // after some code on the same line
+            continue;
+        }
+        match kind {
+            // a shebang line isn't a comment to rewrap
+            Kind::Line(_) if range.start == 0 && source.starts_with("#!") => {}
+            Kind::Line(marker) => {
+                match (previous, regions.last_mut()) {
+                    (Some((line, m, i)), Some(Region::Comment(region)))
+                        if line + 1 == first && m == marker && i == indentation =>
+                    {
+                        region.end = first + 1
+                    }
+                    _ => regions.push(Region::Comment(first..first + 1)),
+                }
+                previous = Some((first, marker, indentation));
+            }
+            _ => {
+                if last > first + 1
+                    && source[range.end..after].trim().is_empty()
+                    && is_comment_opener(line_text(first))
+                    && is_comment_closer(line_text(last))
+                {
+                    regions.push(Region::Comment(first..last + 1));
+                }
+                previous = None;
+            }
+        }
+    }
+    regions
+}
+
+/// Rewrap the comments and docstrings in a source file, leaving code and
+/// other string literals alone. Comments that share a line with code aren't
+/// touched either.
+pub fn rewrapped(
+    opts: &FormatOpts,
+    lang: &Language,
+    source: &str,
+) -> Result<Vec<Rewrapped>, Error> {
+    let mut starts = vec![0];
+    starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+    if starts.last() == Some(&source.len()) {
+        starts.pop();
+    }
+    let regions = comment_regions(lang, source, &starts);
+
+    let opts = FormatOpts {
+        format_mode: FormatMode::Code,
+        ..opts.clone()
+    };
+    crate::map_in_order(regions, |region| {
+        let (lines, docstring) = match region {
+            Region::Comment(lines) => (lines, false),
+            Region::Docstring(lines) => (lines, true),
+        };
+        let start = starts[lines.start];
+        let end = starts.get(lines.end).map_or(source.len(), |&next| next - 1);
+        let end = if source[..end].ends_with('\r') {
+            end - 1
+        } else {
+            end
+        };
+        let text = &source[start..end];
+        // the indentation is put back afterwards, so that tabs stay tabs
+        let indentation = common_indentation(text);
+        let indent_width: usize = indentation
+            .chars()
+            .map(|c| if c == '\t' { opts.tab_width } else { 1 })
+            .sum();
+        let inner = FormatOpts {
+            max_length: opts.max_length.saturating_sub(indent_width).max(1),
+            ..opts.clone()
+        };
+        let dedented: Vec<&str> = text
+            .lines()
+            .map(|line| line.strip_prefix(indentation).unwrap_or(line.trim_start()))
+            .collect();
+        let (reformatted, diagnostics) = reformat::with_diagnostics(&inner, |inner| {
+            if docstring {
+                let expanded = dedented
+                    .join("\n")
+                    .replace('\t', &" ".repeat(inner.tab_width));
+                docstring::reformat(inner, &expanded)
+            } else {
+                reformat::reformat_unchecked(inner, &dedented.join("\n"))
+            }
+        });
+        let text = reformatted?
+            .split('\n')
+            .map(|line| {
+                if line.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}{}", indentation, line)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Ok(Rewrapped {
+            range: start..end,
+            text,
+            diagnostics,
+        })
+    })
+    .into_iter()
+    .collect()
+}
+
+/// The whitespace at the start of every line of `text` that isn't blank
+fn common_indentation(text: &str) -> &str {
+    let mut common: Option<&str> = None;
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let indentation = &line[..line.len() - line.trim_start().len()];
+        common = Some(match common {
+            None => indentation,
+            Some(c) => {
+                let shared = c
+                    .char_indices()
+                    .zip(indentation.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(c.len().min(indentation.len()), |((i, _), _)| i);
+                &c[..shared]
+            }
+        });
+    }
+    common.unwrap_or("")
+}
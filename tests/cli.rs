@@ -30,7 +30,7 @@ fn test_width_flag() {
         .write_stdin("We the people of the United States, in order to form a more perfect union.")
         .assert()
         .success()
-        .stdout("We the people of the United\nStates, in order to form a\nmore perfect union.\n");
+        .stdout("We the people of the United\nStates, in order to form a\nmore perfect union.");
 }
 
 #[test]
@@ -96,3 +96,314 @@ fn test_nonexistent_file() {
 fn test_empty_stdin() {
     prose_cmd().write_stdin("").assert().success().stdout("");
 }
+
+/// A fresh, empty scratch directory for tests that write files
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("prose-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+const LONG_LINE: &str =
+    "We the people of the United States, in order to form a more perfect union.\n";
+const WRAPPED_30: &str =
+    "We the people of the United\nStates, in order to form a\nmore perfect union.\n";
+
+#[test]
+fn test_in_place() {
+    let dir = scratch_dir("in-place");
+    let a = dir.join("a.txt");
+    let b = dir.join("b.txt");
+    std::fs::write(&a, LONG_LINE).unwrap();
+    std::fs::write(&b, LONG_LINE).unwrap();
+
+    prose_cmd()
+        .args(["-i", "-w", "30"])
+        .arg(&a)
+        .arg(&b)
+        .assert()
+        .success()
+        .stdout("");
+
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), WRAPPED_30);
+    assert_eq!(std::fs::read_to_string(&b).unwrap(), WRAPPED_30);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+}
+
+#[test]
+fn test_in_place_backup() {
+    let dir = scratch_dir("in-place-backup");
+    let a = dir.join("a.txt");
+    std::fs::write(&a, LONG_LINE).unwrap();
+
+    prose_cmd()
+        .args(["-i", "--backup-suffix", ".orig", "-w", "30"])
+        .arg(&a)
+        .assert()
+        .success();
+
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), WRAPPED_30);
+    assert_eq!(
+        std::fs::read_to_string(dir.join("a.txt.orig")).unwrap(),
+        LONG_LINE
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_in_place_keeps_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = scratch_dir("in-place-permissions");
+    let a = dir.join("a.sh");
+    std::fs::write(&a, LONG_LINE).unwrap();
+    std::fs::set_permissions(&a, std::fs::Permissions::from_mode(0o750)).unwrap();
+
+    prose_cmd()
+        .args(["-i", "-w", "30"])
+        .arg(&a)
+        .assert()
+        .success();
+
+    let mode = std::fs::metadata(&a).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o750);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_in_place_keeps_symlink() {
+    let dir = scratch_dir("in-place-symlink");
+    let a = dir.join("a.txt");
+    let link = dir.join("link.txt");
+    std::fs::write(&a, LONG_LINE).unwrap();
+    std::os::unix::fs::symlink("a.txt", &link).unwrap();
+
+    prose_cmd()
+        .args(["-i", "-w", "30"])
+        .arg(&link)
+        .assert()
+        .success();
+
+    assert!(std::fs::symlink_metadata(&link).unwrap().is_symlink());
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), WRAPPED_30);
+}
+
+#[test]
+fn test_in_place_continues_after_error() {
+    let dir = scratch_dir("in-place-error");
+    let a = dir.join("a.txt");
+    std::fs::write(&a, LONG_LINE).unwrap();
+
+    prose_cmd()
+        .args(["-i", "-w", "30"])
+        .arg(dir.join("missing.txt"))
+        .arg(&a)
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("missing.txt"));
+
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), WRAPPED_30);
+}
+
+#[test]
+fn test_backup_suffix_requires_in_place() {
+    prose_cmd()
+        .args(["--backup-suffix", ".orig"])
+        .write_stdin("")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_check_formatted() {
+    prose_cmd()
+        .args(["--check", "-w", "30"])
+        .write_stdin(WRAPPED_30)
+        .assert()
+        .success()
+        .stdout("");
+}
+
+#[test]
+fn test_check_unformatted() {
+    let dir = scratch_dir("check");
+    let a = dir.join("a.txt");
+    std::fs::write(&a, LONG_LINE).unwrap();
+
+    prose_cmd()
+        .args(["--check", "-w", "30"])
+        .arg(&a)
+        .assert()
+        .code(1)
+        .stdout(predicates::str::ends_with("a.txt: line 1\n"));
+
+    assert_eq!(std::fs::read_to_string(&a).unwrap(), LONG_LINE);
+}
+
+#[test]
+fn test_diff() {
+    prose_cmd()
+        .args(["--diff", "-w", "30"])
+        .write_stdin(LONG_LINE)
+        .assert()
+        .success()
+        .stdout(
+            "--- <stdin>\n+++ <stdin>\n@@ -1,1 +1,3 @@\n\
+             -We the people of the United States, in order to form a more perfect union.\n\
+             +We the people of the United\n+States, in order to form a\n+more perfect union.\n",
+        );
+}
+
+#[test]
+fn test_diff_no_newline_at_end() {
+    prose_cmd()
+        .args(["--diff", "-w", "30"])
+        .write_stdin(LONG_LINE.trim_end())
+        .assert()
+        .success()
+        .stdout(
+            "--- <stdin>\n+++ <stdin>\n@@ -1,1 +1,3 @@\n\
+             -We the people of the United States, in order to form a more perfect union.\n\
+             \\ No newline at end of file\n\
+             +We the people of the United\n+States, in order to form a\n+more perfect union.\n\
+             \\ No newline at end of file\n",
+        );
+}
+
+#[test]
+fn test_diff_crlf() {
+    prose_cmd()
+        .args(["--diff", "-w", "30"])
+        .write_stdin(LONG_LINE.replace('\n', "\r\n"))
+        .assert()
+        .success()
+        .stdout(
+            "--- <stdin>\n+++ <stdin>\n@@ -1,1 +1,3 @@\n\
+             -We the people of the United States, in order to form a more perfect union.\r\n\
+             +We the people of the United\r\n+States, in order to form a\r\n+more perfect union.\r\n",
+        );
+}
+
+#[test]
+fn test_lines() {
+    let input = format!("{}\n{}", LONG_LINE, LONG_LINE);
+    prose_cmd()
+        .args(["--lines", "3:3", "-w", "30"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(format!("{}\n{}", LONG_LINE, WRAPPED_30));
+}
+
+#[test]
+fn test_lines_invalid_range() {
+    prose_cmd()
+        .args(["--lines", "5:2"])
+        .write_stdin("")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("START <= END"));
+}
+
+#[test]
+fn test_line_ending() {
+    prose_cmd()
+        .args(["-w", "30"])
+        .write_stdin(LONG_LINE.replace('\n', "\r\n"))
+        .assert()
+        .success()
+        .stdout(WRAPPED_30.replace('\n', "\r\n"));
+    prose_cmd()
+        .args(["-w", "30", "--line-ending", "lf"])
+        .write_stdin(LONG_LINE.replace('\n', "\r\n"))
+        .assert()
+        .success()
+        .stdout(WRAPPED_30);
+}
+
+#[test]
+fn test_overflow_warning() {
+    prose_cmd()
+        .args(["-w", "10"])
+        .write_stdin("a supercalifragilistic word")
+        .assert()
+        .success()
+        .stdout("a\nsupercalifragilistic\nword")
+        .stderr(
+            "Warning: <stdin>: line 2: allowing `supercalifragilistic` to extend beyond the target width\n",
+        );
+}
+
+#[test]
+fn test_long_words() {
+    prose_cmd()
+        .args(["-w", "20", "--long-words", "break-at-url-points"])
+        .write_stdin("see https://example.com/docs/some/long/path for details\n")
+        .assert()
+        .success()
+        .stdout("see https://\nexample.com/docs/\nsome/long/path for\ndetails\n");
+    prose_cmd()
+        .args(["-w", "20", "--long-words", "error"])
+        .write_stdin("see https://example.com/docs/some/long/path for details\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("more than the 20 available"));
+}
+
+#[test]
+fn test_stream() {
+    let input = format!("{}\n{}", LONG_LINE, LONG_LINE);
+    prose_cmd()
+        .args(["--stream", "100", "-w", "30"])
+        .write_stdin(input)
+        .assert()
+        .success()
+        .stdout(format!("{}\n{}", WRAPPED_30, WRAPPED_30));
+}
+
+#[test]
+fn test_code_flag() {
+    let dir = scratch_dir("code");
+    let source = dir.join("lib.rs");
+    std::fs::write(
+        &source,
+        "// We the people of the United States, in order to form a more perfect union.\nfn f() {}\n",
+    )
+    .unwrap();
+    prose_cmd()
+        .args(["--code", "-w", "30"])
+        .arg(&source)
+        .assert()
+        .success()
+        .stdout("// We the people of the United\n// States, in order to form a\n// more perfect union.\nfn f() {}\n");
+    prose_cmd()
+        .args(["--code", "--lang", "c", "-w", "30"])
+        .write_stdin("int x; // We the people of the United States\n")
+        .assert()
+        .success()
+        .stdout("int x; // We the people of the United States\n");
+    prose_cmd()
+        .args(["--code"])
+        .write_stdin("# a comment\n")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("use --lang"));
+}
+
+#[test]
+fn test_git_commit_flag() {
+    prose_cmd()
+        .args(["--git-commit", "-w", "30"])
+        .write_stdin(format!(
+            "Add a preamble\n\n{}\nSigned-off-by: A U Thor <author@example.com>\n",
+            LONG_LINE
+        ))
+        .assert()
+        .success()
+        .stdout(format!(
+            "Add a preamble\n\n{}\nSigned-off-by: A U Thor <author@example.com>\n",
+            WRAPPED_30
+        ))
+        .stderr("");
+}
@@ -0,0 +1,24 @@
+#!/usr/bin/env run-cargo-script
+//! A crate-level doc comment that goes on for quite a while and should be rewrapped to the width.
+
+use std::fmt;
+
+/// Joins two strings. This comment is long enough that it needs to be wrapped onto more lines.
+/// And it carries on here.
+fn join<'a>(a: &'a str, b: &str) -> String {
+    // a comment inside a function body, indented, which is long enough to be rewrapped as well
+    // as this second line
+    let url = "http://example.com/not//a/comment // really"; // trailing comment that is long and should not be touched at all
+    let raw = r#"a "raw" string // with slashes"#;
+    let c = '"'; // quote char
+    let q = '\'';
+    let bytes = br"C:\";
+    let text = "
+// text inside a multi-line string, which is long enough that it would be rewrapped
+";
+    /*
+     * A block comment whose lines are all rather short
+     * and should be joined together into one paragraph.
+     */
+    format!("{}{}{}{}{}", a, b, url, raw, c)
+}
@@ -0,0 +1,20 @@
+    /// Returns the sum of the numbers, which is a long
+    /// sentence that will certainly need to be wrapped
+    /// here.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use foo::sum;
+    /// let total = sum(&[1, 2, 3]); // a long line inside the example that must stay as it is
+    /// assert_eq!(total, 6);
+    /// ```
+    ///
+    /// - first item in a list that is long enough that it
+    ///   should wrap around to the next line
+    /// - second item
+    ///
+    /// See [`Vec`] and [the docs] for more details on how
+    /// this function behaves with large inputs.
+    ///
+    /// [the docs]: https://doc.rust-lang.org/std/vec/struct.Vec.html
@@ -0,0 +1,85 @@
+#!/usr/bin/env python3
+"""Module docstring.
+
+This module does a number of things that take rather a lot
+of words to describe in full detail here.
+"""
+
+SQL = """
+select * from a very long table name where something is true and something else is also quite true
+"""
+
+
+def google(x, y):
+    """Add two numbers together.
+
+    This function adds two numbers. It is documented with
+    a long paragraph that should be rewrapped to the
+    configured width.
+
+    Args:
+        x (int): The first number, which is described
+            with enough words that it must wrap.
+        y (int): The second number. Its description
+            already continues on an indented line, and then
+            goes on for a while.
+
+    Returns:
+        int: The sum of the two numbers, described at such
+        length that the line needs wrapping.
+
+    Example:
+        >>> google(1, 2)
+        3
+    """
+    return x + y
+
+
+def numpy(a):
+    r"""Do numpy things.
+
+    Parameters
+    ----------
+    a : array_like, a long type description that stays on its own line whatever its length
+        The input array, whose description is long enough to
+        need wrapping at this width.
+
+    Returns
+    -------
+    ndarray
+        The result.
+    """
+
+
+def rest(a, b):
+    '''Do reST things.
+
+    :param a: the first argument, described at length so
+        that it has to wrap onto another line
+    :type a: int
+    :param b: the second argument
+    :returns: nothing at all, but says so using far too
+        many words for one line
+
+    Literal block::
+
+        keep    this   exactly as it is, even though it is a rather long line of text
+    '''
+
+
+def main():
+    """Run the tool.
+
+    Usage:
+        tool --verbose FILE
+        tool --help
+
+    Options:
+        -v  Be verbose.
+        -h  Show help.
+
+    Function spam(eggs):
+        Return the spam, described at such great length that the line is far too long.
+    Function ham():
+        Return the ham.
+    """
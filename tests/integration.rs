@@ -2,8 +2,12 @@ extern crate prose;
 
 mod diff;
 
-use prose::{FormatMode, FormatOpts};
+use prose::{
+    Alignment, CostModel, Diagnostic, Error, FormatMode, FormatOpts, Language, LastLine,
+    LineEnding, LongWordPolicy, Solver,
+};
 use std::io::Cursor;
+use std::sync::mpsc;
 
 fn process_to_string(input: &str, opts: FormatOpts) -> String {
     let mut out = Vec::<u8>::new();
@@ -27,6 +31,39 @@ fn test_one_line() {
     assert_eq!(actual, "Lot's of string data...\nto be reformatted");
 }
 
+#[test]
+fn test_no_line_starts_with_marker() {
+    let data = "We ran the new tokenizer on the full evaluation corpus - the results were much better than expected, and 3. points were gained on the benchmark overall.";
+    for width in 20..80 {
+        let opts = FormatOpts::with_max_length(width);
+        let once = prose::reformat(&opts, data);
+        assert_eq!(prose::reformat(&opts, &once), once, "width {}", width);
+    }
+}
+
+#[test]
+fn test_markdown_no_line_starts_block() {
+    let data = "This paragraph contains a dash - in the middle, then # a hash, > an angle bracket, a 1. numbered thing, === and ``` too.\n";
+    for width in 15..80 {
+        let opts = FormatOpts {
+            format_mode: FormatMode::Markdown,
+            ..FormatOpts::with_max_length(width)
+        };
+        let once = prose::reformat(&opts, data);
+        for line in once.lines() {
+            assert!(
+                !line.starts_with(['-', '#', '>', '='])
+                    && !line.starts_with("1.")
+                    && !line.starts_with("```"),
+                "width {}: {:?}",
+                width,
+                line
+            );
+        }
+        assert_eq!(prose::reformat(&opts, &once), once, "width {}", width);
+    }
+}
+
 #[test]
 fn test_widths() {
     let opts = FormatOpts::with_max_length(40);
@@ -162,6 +199,120 @@ fn test_rust_comments() {
     assert_diff!(expected, &actual);
 }
 
+#[test]
+fn process_test_block_comments() {
+    let data = include_str!("data/inputs/block_comments.txt");
+    let opts = FormatOpts {
+        max_length: 50,
+        format_mode: FormatMode::Code,
+        ..Default::default()
+    };
+    let actual = process_to_string(data, opts.clone());
+    assert_diff!(include_str!("data/outputs/block_comments_50.txt"), &actual);
+    assert_diff!(&actual, &stream_to_string(data, opts, 8));
+}
+
+#[test]
+fn process_test_doc_comments() {
+    let data = include_str!("data/inputs/doc_comments.rs");
+    let opts = FormatOpts {
+        max_length: 60,
+        format_mode: FormatMode::Code,
+        ..Default::default()
+    };
+    let expected = include_str!("data/outputs/doc_comments_60.rs");
+    assert_diff!(expected, &process_to_string(data, opts.clone()));
+    assert_diff!(expected, &stream_to_string(data, opts, 8));
+}
+
+#[test]
+fn process_test_source_comments() {
+    let actual = process_to_string(
+        include_str!("data/inputs/source_comments.rs"),
+        FormatOpts {
+            max_length: 60,
+            format_mode: FormatMode::Source(Language::named("rust").unwrap()),
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/source_comments_60.rs"), &actual);
+}
+
+#[test]
+fn process_test_docstrings() {
+    let actual = process_to_string(
+        include_str!("data/inputs/docstrings.py"),
+        FormatOpts {
+            max_length: 60,
+            format_mode: FormatMode::Source(Language::named("python").unwrap()),
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/docstrings_60.py"), &actual);
+}
+
+#[test]
+fn process_test_commit_message() {
+    let actual = process_to_string(
+        include_str!("data/inputs/commit_msg.txt"),
+        FormatOpts {
+            format_mode: FormatMode::GitCommit,
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/commit_msg_72.txt"), &actual);
+}
+
+#[test]
+fn test_commit_subject() {
+    let (sender, receiver) = mpsc::channel();
+    let opts = FormatOpts {
+        format_mode: FormatMode::GitCommit,
+        diagnostics: Some(sender),
+        ..FormatOpts::with_max_length(30)
+    };
+    let data =
+        "Fix the widget so that it no longer breaks on Tuesdays\nIt used to break every week.\n";
+    assert_eq!(
+        prose::try_reformat(&opts, data).unwrap(),
+        "Fix the widget so that it no longer breaks on Tuesdays\n\nIt used to break every week."
+    );
+    drop(opts);
+    assert_eq!(
+        receiver.iter().collect::<Vec<_>>(),
+        vec![Diagnostic::LongSubject {
+            line: 1,
+            width: 54,
+            limit: 50,
+        }]
+    );
+}
+
+#[test]
+fn test_comment_marker_before_multibyte() {
+    let opts = FormatOpts {
+        max_length: 20,
+        format_mode: FormatMode::Source(Language::named("rust").unwrap()),
+        ..Default::default()
+    };
+    let data = "//—a comment long enough to wrap\n//—and another line\nfn f() {}\n";
+    assert_eq!(
+        prose::reformat(&opts, data),
+        "//—a comment long\n//—enough to wrap\n//—and another line\nfn f() {}"
+    );
+}
+
+#[test]
+fn test_source_strings_untouched() {
+    let opts = FormatOpts {
+        max_length: 20,
+        format_mode: FormatMode::Source(Language::named("python").unwrap()),
+        ..Default::default()
+    };
+    let data = "s = \"\"\"\n# not a comment, though it is rather long\n\"\"\"\n";
+    assert_eq!(prose::reformat(&opts, data), data.trim_end());
+}
+
 #[test]
 fn process_test_widths() {
     let actual = process_to_string(
@@ -180,6 +331,47 @@ fn process_test_comments_regression() {
     assert_diff!(include_str!("data/outputs/comments_40.txt"), &actual);
 }
 
+#[test]
+fn process_test_justify() {
+    let actual = process_to_string(
+        include_str!("data/inputs/comments.txt"),
+        FormatOpts {
+            max_length: 40,
+            justify: true,
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/comments_40_j.txt"), &actual);
+}
+
+#[test]
+fn process_test_center() {
+    let actual = process_to_string(
+        include_str!("data/inputs/comments.txt"),
+        FormatOpts {
+            max_length: 40,
+            alignment: Alignment::Center,
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/comments_40_c.txt"), &actual);
+}
+
+#[test]
+fn test_right_align() {
+    let opts = FormatOpts {
+        max_length: 25,
+        alignment: Alignment::Right,
+        ..Default::default()
+    };
+    let data = "Lot's of string data... to be reformatted";
+    let actual = prose::reformat(&opts, data);
+    assert_eq!(
+        actual,
+        "  Lot's of string data...\n        to be reformatted"
+    );
+}
+
 #[test]
 fn process_test_plain_last_line() {
     let actual = process_to_string(
@@ -193,6 +385,167 @@ fn process_test_plain_last_line() {
     assert_diff!(include_str!("data/outputs/plain_56_l.txt"), &actual);
 }
 
+#[test]
+fn process_test_cost_model_last_line() {
+    let actual = process_to_string(
+        include_str!("data/inputs/plain.txt"),
+        FormatOpts {
+            max_length: 56,
+            cost_model: CostModel {
+                last_line: LastLine::Slack,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/plain_56_l.txt"), &actual);
+}
+
+#[test]
+fn test_cost_model_fit_penalty() {
+    let data = include_str!("data/inputs/plain_indented.txt");
+    let plain = prose::reformat(&FormatOpts::with_max_length(50), data);
+    let fit = FormatOpts {
+        max_length: 50,
+        reduce_jaggedness: true,
+        ..Default::default()
+    };
+    assert_ne!(prose::reformat(&fit, data), plain);
+    // narrowing the width is never worth it when it's this expensive
+    let penalized = FormatOpts {
+        cost_model: CostModel {
+            fit_penalty: 1_000_000,
+            ..Default::default()
+        },
+        ..fit
+    };
+    assert_eq!(prose::reformat(&penalized, data), plain);
+}
+
+const RELEASE_NOTE: &str = "Release notes are short. They should still look good when wrapped to narrow widths, even here.";
+
+fn reformat_with_costs(max_length: usize, cost_model: CostModel) -> String {
+    let opts = FormatOpts {
+        max_length,
+        cost_model,
+        ..Default::default()
+    };
+    prose::reformat(&opts, RELEASE_NOTE)
+}
+
+#[test]
+fn test_widow_penalty() {
+    let plain = reformat_with_costs(24, CostModel::default());
+    assert!(plain.ends_with("\nhere."));
+    let penalized = reformat_with_costs(
+        24,
+        CostModel {
+            widow_penalty: 1_000,
+            ..Default::default()
+        },
+    );
+    assert!(penalized.lines().last().unwrap().contains(' '));
+}
+
+#[test]
+fn test_sentence_start_penalty() {
+    let plain = reformat_with_costs(16, CostModel::default());
+    assert!(plain.lines().any(|l| l.ends_with(". They")));
+    let penalized = reformat_with_costs(
+        16,
+        CostModel {
+            sentence_start_penalty: 1_000,
+            ..Default::default()
+        },
+    );
+    assert!(!penalized.lines().any(|l| l.ends_with(". They")));
+}
+
+#[test]
+fn test_sentence_start_penalty_first_word() {
+    let data = "Friends go leaves home to walk the dog and then come back later today.";
+    let plain = prose::reformat(&FormatOpts::with_max_length(10), data);
+    assert!(plain.starts_with("Friends\n"));
+    let opts = FormatOpts {
+        cost_model: CostModel {
+            sentence_start_penalty: 1_000_000,
+            ..Default::default()
+        },
+        ..FormatOpts::with_max_length(10)
+    };
+    assert!(prose::reformat(&opts, data).starts_with("Friends go\n"));
+}
+
+#[test]
+fn test_last_line_min_fill() {
+    let filled = reformat_with_costs(
+        24,
+        CostModel {
+            last_line_min_fill: 0.5,
+            ..Default::default()
+        },
+    );
+    assert!(filled.lines().last().unwrap().len() >= 12);
+}
+
+#[test]
+fn test_soft_hyphens() {
+    let opts = FormatOpts {
+        max_length: 10,
+        ..Default::default()
+    };
+    let data = "Quite incompre\u{ad}hen\u{ad}sible.";
+    let result = prose::reformat(&opts, data);
+    assert_eq!(result, "Quite\nincompre-\nhen\u{ad}sible.");
+}
+
+#[cfg(feature = "hyphenation")]
+#[test]
+fn test_hyphenation() {
+    let data = "Typesetting in narrow columns needs hyphenation.";
+    let opts = FormatOpts {
+        max_length: 10,
+        ..Default::default()
+    };
+    assert!(prose::reformat(&opts, data).lines().any(|l| l.len() > 10));
+    let opts = FormatOpts {
+        hyphenate: true,
+        ..opts
+    };
+    let result = prose::reformat(&opts, data);
+    assert!(result.lines().all(|l| l.len() <= 10));
+    assert!(result.lines().any(|l| l.ends_with('-')));
+    assert_eq!(result.replace("-\n", "").replace('\n', " "), data);
+}
+
+#[test]
+fn test_ansi_escapes() {
+    let data = "# plain \x1b[1;31mbold red words\x1b[0m and \x1b]8;;https://example.com\x1b\\a link\x1b]8;;\x1b\\";
+    let opts = FormatOpts {
+        max_length: 16,
+        format_mode: FormatMode::Code,
+        ansi: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        prose::reformat(&opts, data),
+        "# plain \x1b[1;31mbold red\x1b[0m\n\
+         # \x1b[1;31mwords\x1b[0m and \x1b]8;;https://example.com\x1b\\a\x1b]8;;\x1b\\\n\
+         # \x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"
+    );
+}
+
+#[test]
+fn test_affixes_keep_grapheme_clusters() {
+    let opts = FormatOpts::with_max_length(40);
+    // the lines share a base character, but not its combining vowel sign
+    let data = "किताब पढ़ो और\nकल आना";
+    assert_eq!(prose::reformat(&opts, data), "किताब पढ़ो और कल आना");
+    // and these share the start of a ZWJ sequence
+    let data = "👨‍👩‍👧 one two\n👨‍👩‍👦 three four";
+    assert_eq!(prose::reformat(&opts, data), "👨‍👩‍👧 one two 👨‍👩‍👦 three four");
+}
+
 #[test]
 fn process_test_aggressive_fit() {
     let actual = process_to_string(
@@ -209,6 +562,32 @@ fn process_test_aggressive_fit() {
     );
 }
 
+#[test]
+fn process_test_dp_solver() {
+    let actual = process_to_string(
+        include_str!("data/inputs/plain_indented.txt"),
+        FormatOpts {
+            max_length: 50,
+            reduce_jaggedness: true,
+            solver: Solver::DynamicProgramming,
+            ..Default::default()
+        },
+    );
+    assert_diff!(
+        include_str!("data/outputs/plain_indented_50_f.txt"),
+        &actual
+    );
+    let actual = process_to_string(
+        include_str!("data/inputs/lists.txt"),
+        FormatOpts {
+            max_length: 40,
+            solver: Solver::DynamicProgramming,
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/lists_40.txt"), &actual);
+}
+
 #[test]
 fn process_test_tab_expansion() {
     let actual = process_to_string(
@@ -245,6 +624,24 @@ fn process_test_emoji() {
     assert_diff!(include_str!("data/outputs/emoji_40.txt"), &actual);
 }
 
+#[test]
+fn process_test_cjk() {
+    let actual = process_to_string(
+        include_str!("data/inputs/cjk.txt"),
+        FormatOpts::with_max_length(30),
+    );
+    assert_diff!(include_str!("data/outputs/cjk_30.txt"), &actual);
+}
+
+#[test]
+fn process_test_lists() {
+    let actual = process_to_string(
+        include_str!("data/inputs/lists.txt"),
+        FormatOpts::with_max_length(40),
+    );
+    assert_diff!(include_str!("data/outputs/lists_40.txt"), &actual);
+}
+
 #[test]
 fn process_test_markdown() {
     let actual = process_to_string(
@@ -258,6 +655,19 @@ fn process_test_markdown() {
     assert_diff!(include_str!("data/outputs/markdown_53.md"), &actual);
 }
 
+#[test]
+fn process_test_markdown_nested() {
+    let actual = process_to_string(
+        include_str!("data/inputs/markdown_nested.md"),
+        FormatOpts {
+            max_length: 40,
+            format_mode: FormatMode::Markdown,
+            ..Default::default()
+        },
+    );
+    assert_diff!(include_str!("data/outputs/markdown_nested_40.md"), &actual);
+}
+
 #[test]
 fn process_test_rust_comments() {
     let actual = process_to_string(
@@ -283,3 +693,284 @@ fn process_test_yaml_comments() {
     );
     assert_diff!(include_str!("data/outputs/comments_53.yml"), &actual);
 }
+
+#[test]
+fn check_formatted_input() {
+    let data = include_str!("data/outputs/comments_40.txt");
+    let changes =
+        prose::check_paragraphs(&mut Cursor::new(data), FormatOpts::with_max_length(40)).unwrap();
+    assert!(changes.is_empty());
+}
+
+#[test]
+fn check_unformatted_input() {
+    let data = "Already short.\n\nThis paragraph is much too long\nto fit into twenty columns.\n";
+    let changes =
+        prose::check_paragraphs(&mut Cursor::new(data), FormatOpts::with_max_length(20)).unwrap();
+    assert_eq!(changes, vec![3..=4]);
+}
+
+#[test]
+fn diff_unformatted_input() {
+    let data = "Already short.\n\nThis paragraph is much too long\nto fit into twenty columns.\n\nShort again,\nand a long line.\n";
+    let mut out = Vec::<u8>::new();
+    let changed = prose::diff_paragraphs(
+        &mut Cursor::new(data),
+        &mut out,
+        FormatOpts::with_max_length(20),
+        "data.txt",
+    )
+    .unwrap();
+    assert!(changed);
+    assert_diff!(
+        "--- data.txt
++++ data.txt
+@@ -3,2 +3,3 @@
+-This paragraph is much too long
+-to fit into twenty columns.
++This paragraph is
++much too long to fit
++into twenty columns.
+@@ -6,2 +7,2 @@
+-Short again,
+-and a long line.
++Short again, and a
++long line.
+",
+        &String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn diff_markdown_line_numbers() {
+    let data = "# Title\n\n```\ncode\n\nmore code\n```\n\nA paragraph that is too long.\n";
+    let mut out = Vec::<u8>::new();
+    prose::diff_paragraphs(
+        &mut Cursor::new(data),
+        &mut out,
+        FormatOpts {
+            max_length: 20,
+            format_mode: FormatMode::Markdown,
+            ..Default::default()
+        },
+        "doc.md",
+    )
+    .unwrap();
+    assert_diff!(
+        "--- doc.md
++++ doc.md
+@@ -9,1 +9,2 @@
+-A paragraph that is too long.
++A paragraph that is
++too long.
+",
+        &String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn process_range_leaves_other_lines_alone() {
+    let data = "first paragraph that is long enough to wrap\n\nsecond paragraph that\nis long enough to wrap\r\n  \nthird that is long enough to wrap";
+    let mut out = Vec::<u8>::new();
+    prose::process_paragraphs_in_range(
+        &mut Cursor::new(data),
+        &mut out,
+        FormatOpts::with_max_length(20),
+        4..=4,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "first paragraph that is long enough to wrap\n\nsecond paragraph\nthat is long enough\nto wrap\n  \nthird that is long enough to wrap"
+    );
+}
+
+#[test]
+fn process_keeps_line_endings() {
+    let data = "one two three four\r\nfive six\r\n\r\nseven eight nine ten";
+    let expected = "one two three\r\nfour five six\r\n\r\nseven eight nine\r\nten";
+    let opts = FormatOpts::with_max_length(16);
+    assert_eq!(expected, process_to_string(data, opts.clone()));
+    assert_eq!(expected, stream_to_string(data, opts.clone(), 8));
+    let markdown = FormatOpts {
+        format_mode: FormatMode::Markdown,
+        ..opts.clone()
+    };
+    assert_eq!(expected, process_to_string(data, markdown));
+
+    let lf = "one two three\nfour five six\n\nseven eight nine\nten\n";
+    let opts = FormatOpts {
+        line_ending: Some(LineEnding::Lf),
+        ..opts
+    };
+    let data = format!("{}\r\n", data);
+    assert_eq!(lf, process_to_string(&data, opts.clone()));
+    assert_eq!(lf, stream_to_string(&data, opts.clone(), 8));
+    let mut out = Vec::<u8>::new();
+    prose::process_paragraphs_in_range(&mut Cursor::new(&data), &mut out, opts, 1..=1).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "one two three\nfour five six\n\nseven eight nine ten\n"
+    );
+}
+
+fn stream_to_string(input: &str, opts: FormatOpts, window: usize) -> String {
+    let mut out = Vec::<u8>::new();
+    prose::stream_paragraphs(&mut Cursor::new(input), &mut out, opts, window).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn stream_matches_process() {
+    for data in [
+        include_str!("data/inputs/comments.txt"),
+        include_str!("data/inputs/email.txt"),
+        include_str!("data/inputs/lists.txt"),
+        include_str!("data/inputs/plain_indented.txt"),
+    ] {
+        let opts = FormatOpts::with_max_length(40);
+        assert_diff!(
+            &process_to_string(data, opts.clone()),
+            &stream_to_string(data, opts, 8)
+        );
+    }
+}
+
+#[test]
+fn process_keeps_paragraph_order() {
+    // more paragraphs than are reformatted together in a batch
+    let paragraphs: Vec<String> = (0..600)
+        .map(|i| format!("Paragraph {} has a few words\nspread over two lines.\n", i))
+        .collect();
+    let expected: Vec<String> = (0..600)
+        .map(|i| format!("Paragraph {} has a few words spread over two lines.\n", i))
+        .collect();
+    let opts = FormatOpts::with_max_length(72);
+    assert_diff!(
+        &expected.join("\n"),
+        &process_to_string(&paragraphs.join("\n"), opts.clone())
+    );
+    let markdown = FormatOpts {
+        format_mode: FormatMode::Markdown,
+        ..opts
+    };
+    assert_diff!(
+        &expected.join("\n"),
+        &process_to_string(&paragraphs.join("\n"), markdown)
+    );
+}
+
+#[test]
+fn stream_long_paragraph() {
+    let data = include_str!("data/inputs/plain.txt")
+        .replace("\n\n", "\n")
+        .repeat(30);
+    let actual = stream_to_string(&data, FormatOpts::with_max_length(40), 16);
+    assert!(actual.lines().all(|l| l.len() <= 40));
+    assert_eq!(
+        actual.split_whitespace().collect::<Vec<_>>(),
+        data.split_whitespace().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_invalid_options() {
+    let opts = FormatOpts::with_max_length(0);
+    assert_eq!(
+        prose::try_reformat(&opts, "some text"),
+        Err(Error::InvalidOptions("max_length must be at least 1"))
+    );
+    assert!(prose::Reformatter::try_new(&opts, "some text").is_err());
+    assert_eq!(
+        prose::try_reformat(&FormatOpts::with_max_length(20), "some text"),
+        Ok(String::from("some text"))
+    );
+}
+
+#[test]
+fn test_overflow_diagnostics() {
+    let data = "> see https://example.com/a/very/long/path/to/something for the details";
+    for solver in [Solver::ShortestPath, Solver::DynamicProgramming] {
+        let (sender, receiver) = mpsc::channel();
+        let opts = FormatOpts {
+            diagnostics: Some(sender),
+            solver,
+            ..FormatOpts::with_max_length(20)
+        };
+        assert_eq!(
+            prose::try_reformat(&opts, data).unwrap(),
+            "> see\n> https://example.com/a/very/long/path/to/something\n> for the details"
+        );
+        drop(opts);
+        assert_eq!(
+            receiver.iter().collect::<Vec<_>>(),
+            vec![Diagnostic::Overflow {
+                line: 2,
+                word: String::from("https://example.com/a/very/long/path/to/something"),
+                width: 49,
+                target: 18,
+            }]
+        );
+    }
+}
+
+#[test]
+fn test_long_word_policies() {
+    let data = "see https://example.com/docs/some/long/path for details";
+    let opts = |long_words| FormatOpts {
+        long_words,
+        ..FormatOpts::with_max_length(20)
+    };
+    assert_eq!(
+        prose::reformat(&opts(LongWordPolicy::Break), data),
+        "see https://example.\ncom/docs/some/long/p\nath for details"
+    );
+    assert_eq!(
+        prose::reformat(&opts(LongWordPolicy::BreakAtUrlPoints), data),
+        "see https://\nexample.com/docs/\nsome/long/path for\ndetails"
+    );
+    assert_eq!(
+        prose::try_reformat(&opts(LongWordPolicy::Fail), data),
+        Err(Error::LongWord {
+            word: String::from("https://example.com/docs/some/long/path"),
+            width: 39,
+            target: 20,
+        })
+    );
+    // words that fit are never broken
+    assert_eq!(
+        prose::reformat(&opts(LongWordPolicy::Break), "a short/sweet word"),
+        "a short/sweet word"
+    );
+}
+
+#[test]
+fn test_overflow_report_lines() {
+    let data = "one extraordinarily long word\n\nand another\nextraordinarily long word\n";
+    let (sender, receiver) = mpsc::channel();
+    let opts = FormatOpts {
+        diagnostics: Some(sender),
+        ..FormatOpts::with_max_length(10)
+    };
+    assert_eq!(
+        process_to_string(data, opts.clone()),
+        "one\nextraordinarily\nlong word\n\nand\nanother\nextraordinarily\nlong word\n"
+    );
+    assert_eq!(
+        stream_to_string(data, opts.clone(), 8),
+        "one\nextraordinarily\nlong word\n\nand\nanother\nextraordinarily\nlong word\n"
+    );
+    let markdown = FormatOpts {
+        format_mode: FormatMode::Markdown,
+        ..opts
+    };
+    process_to_string(data, markdown);
+    let lines: Vec<usize> = receiver
+        .try_iter()
+        .map(|d| match d {
+            Diagnostic::Overflow { line, .. } => line,
+            other => panic!("unexpected {:?}", other),
+        })
+        .collect();
+    assert_eq!(lines, vec![2, 7, 2, 7, 2, 7]);
+}